@@ -11,12 +11,14 @@ struct Vertex {
     @builtin(instance_index) instance_index: u32,
     @location(0) position: vec3<f32>,
     @location(1) v: f32,
+    @location(2) d: f32,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) v: f32,
-    @location(1) world_position: vec2<f32>
+    @location(1) world_position: vec2<f32>,
+    @location(2) d: f32,
 };
 
 @vertex
@@ -32,12 +34,14 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
 
     out.v = vertex.v;
+    out.d = vertex.d;
     return out;
 }
 
 struct FragmentInput {
     @location(0) v: f32,
-    @location(1) world_position: vec2<f32>
+    @location(1) world_position: vec2<f32>,
+    @location(2) d: f32,
 };
 
 @fragment
@@ -49,5 +53,7 @@ fn fragment(input: FragmentInput) -> @location(0) vec4<f32> {
     let sinadd = sin((mul * 10) + (t * 3) + input.world_position.x / 5 / 2 + mul + input.world_position.y / 5 / 2 + mul);
     let cosadd = cos((mul * 35) + (t * 2) + input.world_position.x / 25 / 2 + mul + input.world_position.y / 25 / 2 + mul);
     let v3 = v2 + sinadd / 10 - cosadd / 10;
-    return material.color * v3 * pow(1.0 - input.v, 3.0);
+    // glow along the walls, fading out with the distance into the terrain
+    let rim = pow(1.0 - clamp(input.d, 0.0, 1.0), 4.0) * 0.3;
+    return material.color * (v3 * pow(1.0 - input.v, 3.0) + rim);
 }
\ No newline at end of file
@@ -0,0 +1,73 @@
+// checks level files without opening a window:
+//   speedwing-validate [level.json ...]
+// with no arguments every level in the manifest is checked
+
+use std::{fs, path::Path, process::ExitCode};
+
+use speedwing::{
+    level_check::check_level,
+    level_manifest::{LevelManifest, MANIFEST_PATH},
+};
+
+const ASSETS: &str = "assets";
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = match std::env::args().skip(1).collect::<Vec<_>>() {
+        args if !args.is_empty() => args,
+        _ => match manifest_paths() {
+            Ok(paths) => paths,
+            Err(error) => {
+                eprintln!("{}: {}", MANIFEST_PATH, error);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let mut failed = 0;
+    for path in paths.iter() {
+        let problems = validate(Path::new(path));
+        if problems.is_empty() {
+            println!("{}: ok", path);
+        } else {
+            failed += 1;
+            for problem in problems {
+                println!("{}: {}", path, problem);
+            }
+        }
+    }
+
+    println!("{} of {} levels failed", failed, paths.len());
+    match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+fn manifest_paths() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let bytes = fs::read(Path::new(ASSETS).join(MANIFEST_PATH))?;
+    let manifest = serde_json::from_slice::<LevelManifest>(&bytes)?;
+    Ok(manifest
+        .levels
+        .iter()
+        .map(|level| format!("{}/{}", ASSETS, level.path))
+        .collect())
+}
+
+fn validate(path: &Path) -> Vec<String> {
+    match fs::read(path) {
+        Ok(bytes) => check_level(&bytes),
+        Err(error) => vec![error.to_string()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_levels_pass() {
+        for path in manifest_paths().unwrap() {
+            assert_eq!(validate(Path::new(&path)), Vec::<String>::new(), "{}", path);
+        }
+    }
+}
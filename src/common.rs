@@ -1,14 +1,34 @@
 use bevy::{math::bool, prelude::*};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    level_format::{GridParams, LevelMap},
+    replay::Replay,
+    sim::TICK,
+    terrain::GeneratorSettings,
+};
+
+// how close the ship has to get to the finish line to finish
+pub const FINISH_DISTANCE: f32 = 50.;
+
 #[derive(Component)]
 pub struct Velocity(pub Vec3);
 
-#[derive(Resource)]
+// run times are counted in simulation ticks
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
 pub enum TimeState {
-    Finished(f32),
-    Timing(f32),
-    None
+    Finished(u32),
+    Timing(u32),
+    None,
+}
+
+impl TimeState {
+    pub fn seconds(&self) -> f32 {
+        match self {
+            TimeState::None => 0.,
+            TimeState::Timing(ticks) | TimeState::Finished(ticks) => *ticks as f32 * TICK,
+        }
+    }
 }
 
 #[derive(Resource)]
@@ -16,12 +36,18 @@ pub struct State {
     pub debug: bool,
     pub editor: bool,
     pub time: TimeState,
-    pub follow: f32
+    pub follow: f32,
+    // checkpoints passed this run and the tick each was passed on,
+    // next to the best run's to compare against
+    pub checkpoint: usize,
+    pub splits: Vec<u32>,
+    pub best_splits: Vec<u32>,
 }
 
 #[derive(Resource)]
 pub struct CurrentLevel(pub u32, pub LevelData);
 
+#[cfg(feature = "game")]
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub enum SceneState {
     #[default]
@@ -36,21 +62,76 @@ pub struct MainCamera;
 #[derive(Component)]
 pub struct GameEntity;
 
+// buttons marked with T that were pressed, hovered or left this frame
+#[cfg(feature = "game")]
+pub type ButtonQuery<'w, 's, T> = Query<
+    'w,
+    's,
+    (
+        &'static Interaction,
+        &'static mut BackgroundColor,
+        &'static T,
+    ),
+    Changed<Interaction>,
+>;
+
+#[cfg(feature = "game")]
 #[derive(Default, Reflect, GizmoConfigGroup)]
 pub struct MovementGizmoGroup;
 
+#[cfg(feature = "game")]
 #[derive(Default, Reflect, GizmoConfigGroup)]
 pub struct FinishGizmoGroup;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LevelData {
-    pub level: Option<String>,
+    pub level: Option<LevelMap>,
     pub start: [f32; 3],
-    pub end: Option<[[f32; 2]; 2]>
+    pub end: Option<[[f32; 2]; 2]>,
+    // gates to pass in order before the finish counts, left out of the file when
+    // there are none so older levels keep their hash
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checkpoints: Vec<[[f32; 2]; 2]>,
+    #[serde(default)]
+    pub grid: Option<GridParams>,
+    // how the map was generated, if it was
+    #[serde(default)]
+    pub generator: Option<GeneratorSettings>,
+    // the author's run, shown as a ghost
+    #[serde(default)]
+    pub ghost: Option<Replay>,
 }
 
-pub fn get_threshold() -> f32 {
-   0.5
+impl LevelData {
+    // levels saved before grid params were stored only have them in the map header
+    pub fn grid_params(&self) -> GridParams {
+        if let Some(grid) = self.grid {
+            return grid;
+        }
+        match &self.level {
+            Some(LevelMap::Current(save)) => GridParams {
+                scale: save.scale,
+                grid_size: save.grid_size,
+                threshold: save.threshold,
+                ..default()
+            },
+            _ => GridParams::default(),
+        }
+    }
+    // the same for the same level on every run and platform, used to match
+    // replays and records to the level they came from. the author's ghost
+    // isn't part of the level, its replay has to point at the hash itself
+    pub fn content_hash(&self) -> u64 {
+        let level = LevelData {
+            ghost: None,
+            ..self.clone()
+        };
+        let bytes = serde_json::to_vec(&level).unwrap_or_default();
+        // fnv-1a
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
 }
 
 pub fn div_floor(a: i32, b: i32) -> i32 {
@@ -63,16 +144,15 @@ pub fn div_floor(a: i32, b: i32) -> i32 {
     }
 }
 
-pub fn in_viewport(point: Vec2, camera: &Camera, camera_transform: &GlobalTransform) -> bool {
-    if let Ok(viewport_pos) = camera.world_to_viewport(camera_transform, point.extend(0.)) {
-        if let Some(viewport_size) = camera.logical_viewport_size() {
-            return viewport_pos.x >= 0.
-                && viewport_pos.y >= 0.
-                && viewport_pos.x <= viewport_size.x
-                && viewport_pos.y <= viewport_size.y;
-        }
-    }
-    false
+// the part of the world the camera currently shows
+#[cfg(feature = "game")]
+pub fn viewport_rect(camera: &Camera, camera_transform: &GlobalTransform) -> Option<Rect> {
+    let size = camera.logical_viewport_size()?;
+    let a = camera
+        .viewport_to_world_2d(camera_transform, Vec2::ZERO)
+        .ok()?;
+    let b = camera.viewport_to_world_2d(camera_transform, size).ok()?;
+    Some(Rect::from_corners(a, b))
 }
 
 fn dist_to_segment_squared(p: Vec2, v: Vec2, w: Vec2) -> f32 {
@@ -87,4 +167,4 @@ fn dist_to_segment_squared(p: Vec2, v: Vec2, w: Vec2) -> f32 {
 
 pub fn dist_to_segment(p: Vec2, v: Vec2, w: Vec2) -> f32 {
     dist_to_segment_squared(p, v, w).sqrt()
-}
\ No newline at end of file
+}
@@ -0,0 +1,134 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::common::dist_to_segment;
+
+// a grid edge a contour point lies on: the lower node and 0 for horizontal, 1 for vertical
+pub type EdgeKey = (i32, i32, u8);
+
+// a contour segment's two ends, each with the edge it lies on
+pub type Segment = ((Vec2, EdgeKey), (Vec2, EdgeKey));
+
+#[derive(Clone, Debug)]
+pub struct Contour {
+    pub points: Vec<Vec2>,
+    // the last point connects back to the first
+    pub closed: bool,
+}
+
+impl Contour {
+    // joins segments that share a grid edge into polylines
+    pub fn stitch(segments: &[Segment]) -> Vec<Contour> {
+        let mut ends: HashMap<EdgeKey, Vec<usize>> = HashMap::new();
+        for (i, (a, b)) in segments.iter().enumerate() {
+            ends.entry(a.1).or_default().push(i);
+            ends.entry(b.1).or_default().push(i);
+        }
+
+        let mut used = vec![false; segments.len()];
+        let mut contours = Vec::new();
+
+        // open polylines start at an edge only one segment touches, so walk those first
+        let mut starts: Vec<usize> = (0..segments.len())
+            .filter(|&i| {
+                let (a, b) = segments[i];
+                ends[&a.1].len() == 1 || ends[&b.1].len() == 1
+            })
+            .collect();
+        starts.extend(0..segments.len());
+
+        for start in starts {
+            if used[start] {
+                continue;
+            }
+            used[start] = true;
+
+            let (a, b) = segments[start];
+            let (first, mut current) = match ends[&a.1].len() == 1 {
+                true => (a, b),
+                false => (b, a),
+            };
+            let mut points = vec![first.0, current.0];
+
+            let closed = loop {
+                let next = ends[&current.1].iter().copied().find(|&i| !used[i]);
+                let Some(next) = next else {
+                    break current.1 == first.1;
+                };
+                used[next] = true;
+
+                let (a, b) = segments[next];
+                current = if a.1 == current.1 { b } else { a };
+                points.push(current.0);
+            };
+
+            if closed {
+                points.pop();
+            }
+            contours.push(Contour { points, closed });
+        }
+
+        contours
+    }
+
+    // Ramer-Douglas-Peucker, dropping points closer than tolerance to the simplified line
+    pub fn simplify(&mut self, tolerance: f32) {
+        if self.points.len() < 3 {
+            return;
+        }
+
+        if !self.closed {
+            self.points = simplify_polyline(&self.points, tolerance);
+            return;
+        }
+
+        // split the loop at the point furthest from the first and simplify both halves
+        let first = self.points[0];
+        let (split, _) = self
+            .points
+            .iter()
+            .enumerate()
+            .max_by(|a, b| first.distance(*a.1).total_cmp(&first.distance(*b.1)))
+            .unwrap();
+        if split == 0 {
+            return;
+        }
+
+        let mut a = simplify_polyline(&self.points[..=split], tolerance);
+        let mut rest = self.points[split..].to_vec();
+        rest.push(first);
+        let b = simplify_polyline(&rest, tolerance);
+
+        a.pop();
+        a.extend_from_slice(&b[..b.len() - 1]);
+        self.points = a;
+    }
+}
+
+fn simplify_polyline(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        let mut furthest = (0., start);
+        for i in (start + 1)..end {
+            let d = dist_to_segment(points[i], points[start], points[end]);
+            if d > furthest.0 {
+                furthest = (d, i);
+            }
+        }
+        if furthest.0 > tolerance {
+            keep[furthest.1] = true;
+            stack.push((start, furthest.1));
+            stack.push((furthest.1, end));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(point, _)| *point)
+        .collect()
+}
@@ -0,0 +1,591 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::{
+    common::{dist_to_segment, div_floor},
+    contour::{Contour, EdgeKey},
+    level_format::{
+        CellError, ChunkSave, GridParams, LEVEL_FORMAT_VERSION, LevelError, LevelMap, MapSave,
+        check_cells, decode_cells, decode_legacy_cells, encode_cells,
+    },
+    terrain::TerrainGenerator,
+};
+
+// distances further than this many cells from the surface are clamped
+pub const SDF_BAND: f32 = 4.;
+
+// whether the centre of a saddle cell is inside the terrain, from the value at
+// the saddle point of the bilinear interpolant (the asymptotic decider)
+// vs in corner order (x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)
+pub fn saddle_solid(vs: [f32; 4], threshold: f32) -> bool {
+    let denominator = vs[0] + vs[3] - vs[1] - vs[2];
+    let centre = match denominator.abs() < 1e-6 {
+        true => (vs[0] + vs[1] + vs[2] + vs[3]) / 4.,
+        false => (vs[0] * vs[3] - vs[1] * vs[2]) / denominator,
+    };
+    centre > threshold
+}
+
+// the terrain density and its distance field, chunk by chunk. this is all the
+// simulation reads, the meshes drawn from it are kept by GridMap
+#[derive(Clone)]
+pub struct DensityMap {
+    scale: f32,
+    grid_size: u32,
+    chunks: HashMap<(i32, i32), Chunk>,
+    threshold: f32,
+    smooth: bool,
+}
+
+#[derive(Clone)]
+pub struct Chunk {
+    x: f32,
+    y: f32,
+    pub width: u32,
+    pub height: u32,
+    spacing: f32,
+    data: Vec<f32>,
+    // signed distance to the surface in cells at each point, negative inside the terrain
+    sdf: Vec<f32>,
+    sdf_changed: bool,
+    // bumped whenever the cells or distances the chunk's mesh is built from
+    // change, including the neighbouring cells it bridges into
+    pub revision: u64,
+}
+
+impl Chunk {
+    pub fn new(x: f32, y: f32, width: u32, height: u32, spacing: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            spacing,
+            data: vec![0.; (width * height) as usize],
+            sdf: vec![SDF_BAND; (width * height) as usize],
+            sdf_changed: true,
+            revision: 0,
+        }
+    }
+    pub fn get(&self, x: u32, y: u32) -> Option<f32> {
+        if x < self.width && y < self.height {
+            Some(self.data[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+    pub fn get_sdf(&self, x: u32, y: u32) -> Option<f32> {
+        if x < self.width && y < self.height {
+            Some(self.sdf[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+    fn set_sdf(&mut self, sdf: Vec<f32>) {
+        self.sdf = sdf;
+        self.sdf_changed = false;
+        self.revision += 1;
+    }
+    fn set(&mut self, x: u32, y: u32, v: f32) {
+        if x < self.width && y < self.height {
+            self.data[(y * self.width + x) as usize] = v.clamp(0., 1.);
+            self.revision += 1;
+            self.sdf_changed = true;
+        }
+    }
+    pub fn spacing(&self) -> f32 {
+        self.spacing
+    }
+    pub fn rect(&self) -> Rect {
+        Rect::new(
+            self.x,
+            self.y,
+            self.x + self.width as f32 * self.spacing,
+            self.y + self.height as f32 * self.spacing,
+        )
+    }
+    pub fn save(&self) -> String {
+        encode_cells(&self.data)
+    }
+    pub fn load(&mut self, save: &str) -> Result<(), CellError> {
+        self.set_data(decode_cells(save)?)
+    }
+    pub fn load_legacy(&mut self, save: &str) -> Result<(), CellError> {
+        self.set_data(decode_legacy_cells(save)?)
+    }
+    fn set_data(&mut self, data: Vec<f32>) -> Result<(), CellError> {
+        check_cells(&data, (self.width * self.height) as usize)?;
+
+        self.data = data;
+        self.revision += 1;
+        self.sdf_changed = true;
+        Ok(())
+    }
+}
+
+impl DensityMap {
+    pub fn new(params: GridParams) -> Self {
+        Self {
+            scale: params.scale,
+            grid_size: params.grid_size,
+            chunks: HashMap::new(),
+            threshold: params.threshold,
+            smooth: params.smooth,
+        }
+    }
+    pub fn params(&self) -> GridParams {
+        GridParams {
+            scale: self.scale,
+            grid_size: self.grid_size,
+            threshold: self.threshold,
+            smooth: self.smooth,
+        }
+    }
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+    pub fn grid_size(&self) -> u32 {
+        self.grid_size
+    }
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+    pub fn smooth(&self) -> bool {
+        self.smooth
+    }
+    fn create_chunk(&self, x: i32, y: i32) -> Chunk {
+        Chunk::new(
+            x as f32 * self.scale * self.grid_size as f32,
+            y as f32 * self.scale * self.grid_size as f32,
+            self.grid_size,
+            self.grid_size,
+            self.scale,
+        )
+    }
+    pub fn set(&mut self, x: i32, y: i32, v: f32) {
+        let gx = div_floor(x, self.grid_size as i32);
+        let gy = div_floor(y, self.grid_size as i32);
+
+        let x = (x - gx * self.grid_size as i32) as u32;
+        let y = (y - gy * self.grid_size as i32) as u32;
+
+        let Some(chunk) = self.get_or_create(gx, gy) else {
+            return;
+        };
+
+        chunk.set(x, y, v);
+
+        let (x, y) = (
+            x as i32 + gx * self.grid_size as i32,
+            y as i32 + gy * self.grid_size as i32,
+        );
+        self.invalidate_meshes(x, y, x, y);
+        self.invalidate_sdf(x, y, x, y);
+    }
+    // bumps every chunk whose mesh samples points in the given range, which
+    // includes the -x, -y and diagonal neighbours through their bridges
+    fn invalidate_meshes(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        let size = self.grid_size as i32;
+        for gx in div_floor(x0 - 1, size)..=div_floor(x1, size) {
+            for gy in div_floor(y0 - 1, size)..=div_floor(y1, size) {
+                if let Some(chunk) = self.chunks.get_mut(&(gx, gy)) {
+                    chunk.revision += 1;
+                }
+            }
+        }
+    }
+    // marks every chunk with distances that can depend on the points in the given range
+    fn invalidate_sdf(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        let reach = SDF_BAND.ceil() as i32 + 1;
+        let size = self.grid_size as i32;
+        for gx in div_floor(x0 - reach, size)..=div_floor(x1 + reach, size) {
+            for gy in div_floor(y0 - reach, size)..=div_floor(y1 + reach, size) {
+                if let Some(chunk) = self.chunks.get_mut(&(gx, gy)) {
+                    chunk.sdf_changed = true;
+                }
+            }
+        }
+    }
+    // calls f with each contour segment inside the cell at x, y, in cells,
+    // along with the grid edges its ends lie on
+    fn for_cell_segments(
+        &self,
+        x: i32,
+        y: i32,
+        mut f: impl FnMut((Vec2, EdgeKey), (Vec2, EdgeKey)),
+    ) {
+        let vs = [
+            self.get(x, y),
+            self.get(x + 1, y),
+            self.get(x, y + 1),
+            self.get(x + 1, y + 1),
+        ];
+        let mut vsf = [0.; 4];
+        let mut vi = 0u8;
+        for (i, v) in vs.iter().enumerate() {
+            let b = v.is_some_and(|v| v > self.threshold);
+            vsf[i] = v.unwrap_or(0.);
+            vi |= (b as u8) << (i as u8);
+        }
+
+        if vi == 0 || vi == 0b1111 {
+            return;
+        }
+
+        let lerp = |a: f32, b: f32| match self.smooth {
+            true => ((self.threshold - a) / (b - a)).clamp(0., 1.),
+            false => 0.5,
+        };
+
+        let (xi, yi) = (x, y);
+        let x = x as f32;
+        let y = y as f32;
+        let e = [
+            (Vec2::new(x + lerp(vsf[0], vsf[1]), y), (xi, yi, 0)),
+            (Vec2::new(x + 1., y + lerp(vsf[1], vsf[3])), (xi + 1, yi, 1)),
+            (Vec2::new(x + lerp(vsf[2], vsf[3]), y + 1.), (xi, yi + 1, 0)),
+            (Vec2::new(x, y + lerp(vsf[0], vsf[2])), (xi, yi, 1)),
+        ];
+
+        match vi {
+            // corners
+            0b0001 | 0b1110 => f(e[0], e[3]),
+            0b0010 | 0b1101 => f(e[0], e[1]),
+            0b0100 | 0b1011 => f(e[2], e[3]),
+            0b1000 | 0b0111 => f(e[1], e[2]),
+
+            // edges
+            0b0011 | 0b1100 => f(e[1], e[3]),
+            0b0101 | 0b1010 => f(e[0], e[2]),
+
+            // diagonals, cutting off either the solid or the open corners
+            // depending on whether the centre is solid
+            0b1001 | 0b0110 => {
+                if (vi == 0b1001) != saddle_solid(vsf, self.threshold) {
+                    f(e[0], e[3]);
+                    f(e[1], e[2]);
+                } else {
+                    f(e[0], e[1]);
+                    f(e[2], e[3]);
+                }
+            }
+
+            _ => (),
+        }
+    }
+    fn compute_sdf(&self, coords: (i32, i32)) -> Vec<f32> {
+        let size = self.grid_size as i32;
+        let reach = SDF_BAND.ceil() as i32 + 1;
+        let x0 = coords.0 * size;
+        let y0 = coords.1 * size;
+
+        // segments of every cell within reach of the chunk, indexed by cell
+        let span = size + reach * 2;
+        let mut cells = vec![Vec::new(); (span * span) as usize];
+        for cy in 0..span {
+            for cx in 0..span {
+                let cell = &mut cells[(cy * span + cx) as usize];
+                self.for_cell_segments(x0 - reach + cx, y0 - reach + cy, |a, b| {
+                    cell.push((a.0, b.0))
+                });
+            }
+        }
+
+        let mut sdf = Vec::with_capacity((size * size) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let point = Vec2::new((x0 + x) as f32, (y0 + y) as f32);
+                let mut distance = SDF_BAND;
+                for cy in y..(y + reach * 2) {
+                    for cx in x..(x + reach * 2) {
+                        for (a, b) in cells[(cy * span + cx) as usize].iter() {
+                            distance = distance.min(dist_to_segment(point, *a, *b));
+                        }
+                    }
+                }
+
+                let inside = self.get(x0 + x, y0 + y).is_some_and(|v| v > self.threshold);
+                sdf.push(if inside { -distance } else { distance });
+            }
+        }
+        sdf
+    }
+    // recomputes the distances of every chunk edited since the last call
+    pub fn update_sdf(&mut self) {
+        let changed: Vec<(i32, i32)> = self
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| chunk.sdf_changed)
+            .map(|(coords, _)| *coords)
+            .collect();
+
+        let sdfs: Vec<((i32, i32), Vec<f32>)> = changed
+            .into_iter()
+            .map(|coords| (coords, self.compute_sdf(coords)))
+            .collect();
+
+        for (coords, sdf) in sdfs {
+            if let Some(chunk) = self.chunks.get_mut(&coords) {
+                chunk.set_sdf(sdf);
+            }
+        }
+    }
+    pub fn get(&self, x: i32, y: i32) -> Option<f32> {
+        let gx = div_floor(x, self.grid_size as i32);
+        let gy = div_floor(y, self.grid_size as i32);
+
+        let x = (x - gx * self.grid_size as i32) as u32;
+        let y = (y - gy * self.grid_size as i32) as u32;
+
+        match self.chunks.get(&(gx, gy)) {
+            Some(chunk) => chunk.get(x, y),
+            None => None,
+        }
+    }
+    fn gets(&self, x: f32, y: f32) -> Option<f32> {
+        let x0 = x.floor() as i32;
+        let y0 = y.floor() as i32;
+        let x1 = x0 + 1;
+        let y1 = y0 + 1;
+
+        let tx = x.fract();
+        let ty = y.fract();
+
+        let v00 = self.get(x0, y0)?;
+        let v10 = self.get(x1, y0)?;
+        let v01 = self.get(x0, y1)?;
+        let v11 = self.get(x1, y1)?;
+
+        let v = (v00 * (1. - tx) + v10 * tx) * (1. - ty) + (v01 * (1. - tx) + v11 * tx) * ty;
+
+        Some(v)
+    }
+    pub fn get_world(&self, x: f32, y: f32) -> Option<f32> {
+        let gx = x / self.scale;
+        let gy = y / self.scale;
+        self.gets(gx, gy)
+    }
+    pub fn get_sdf(&self, x: i32, y: i32) -> Option<f32> {
+        let gx = div_floor(x, self.grid_size as i32);
+        let gy = div_floor(y, self.grid_size as i32);
+
+        let x = (x - gx * self.grid_size as i32) as u32;
+        let y = (y - gy * self.grid_size as i32) as u32;
+
+        match self.chunks.get(&(gx, gy)) {
+            Some(chunk) => chunk.get_sdf(x, y),
+            None => None,
+        }
+    }
+    // interpolated distance field and its gradient, in cells
+    fn get_distance_gradient(&self, x: f32, y: f32) -> Option<(f32, Vec2)> {
+        let x0 = x.floor() as i32;
+        let y0 = y.floor() as i32;
+
+        let tx = x - x0 as f32;
+        let ty = y - y0 as f32;
+
+        let d00 = self.get_sdf(x0, y0)?;
+        let d10 = self.get_sdf(x0 + 1, y0)?;
+        let d01 = self.get_sdf(x0, y0 + 1)?;
+        let d11 = self.get_sdf(x0 + 1, y0 + 1)?;
+
+        let d = (d00 * (1. - tx) + d10 * tx) * (1. - ty) + (d01 * (1. - tx) + d11 * tx) * ty;
+        let gradient = Vec2::new(
+            (d10 - d00) * (1. - ty) + (d11 - d01) * ty,
+            (d01 - d00) * (1. - tx) + (d11 - d10) * tx,
+        );
+
+        Some((d, gradient))
+    }
+    pub fn get_distance(&self, x: f32, y: f32) -> Option<f32> {
+        self.get_distance_gradient(x, y).map(|(d, _)| d)
+    }
+    // signed distance to the nearest wall in world units, negative inside the terrain
+    pub fn get_distance_world(&self, x: f32, y: f32) -> Option<f32> {
+        self.get_distance(x / self.scale, y / self.scale)
+            .map(|d| d * self.scale)
+    }
+    // unit vector pointing into the terrain
+    fn get_normal(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let (_, gradient) = self.get_distance_gradient(x, y)?;
+        let normal = -gradient.normalize_or_zero();
+        Some((normal.x, normal.y))
+    }
+    pub fn get_normal_world(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let gx = x / self.scale;
+        let gy = y / self.scale;
+        self.get_normal(gx, gy)
+    }
+    // outlines of the terrain in world units, stitched across chunks and
+    // optionally simplified to within tolerance world units
+    pub fn contours(&self, tolerance: Option<f32>) -> Vec<Contour> {
+        self.contours_of(self.chunks.keys().copied(), tolerance)
+    }
+    pub fn contours_of(
+        &self,
+        chunks: impl Iterator<Item = (i32, i32)>,
+        tolerance: Option<f32>,
+    ) -> Vec<Contour> {
+        let size = self.grid_size as i32;
+
+        // every cell with a corner inside one of the chunks, so outlines close around the map edge
+        let mut cells = Vec::new();
+        for coords in chunks {
+            for x in -1..size {
+                for y in -1..size {
+                    cells.push((coords.0 * size + x, coords.1 * size + y));
+                }
+            }
+        }
+        // sorted so the output is stable between calls
+        cells.sort_unstable();
+        cells.dedup();
+
+        let mut segments = Vec::new();
+        for (x, y) in cells {
+            self.for_cell_segments(x, y, |a, b| segments.push((a, b)));
+        }
+
+        let mut contours = Contour::stitch(&segments);
+        for contour in contours.iter_mut() {
+            for point in contour.points.iter_mut() {
+                *point *= self.scale;
+            }
+            if let Some(tolerance) = tolerance {
+                contour.simplify(tolerance);
+            }
+        }
+        contours
+    }
+    pub fn generate(&mut self, gx: i32, gy: i32, generator: &dyn TerrainGenerator) {
+        let size = self.grid_size as i32;
+        let Some(chunk) = self.get_or_create(gx, gy) else {
+            return;
+        };
+
+        let vs = generator.fill(gx * size, gy * size, size as u32, size as u32);
+        for x in 0..size {
+            for y in 0..size {
+                chunk.set(x as u32, y as u32, vs[(y * size + x) as usize] as f32);
+            }
+        }
+
+        let (x0, y0) = (gx * size, gy * size);
+        let (x1, y1) = (x0 + size - 1, y0 + size - 1);
+        self.invalidate_meshes(x0, y0, x1, y1);
+        self.invalidate_sdf(x0, y0, x1, y1);
+    }
+    pub fn contains_chunk(&self, gx: i32, gy: i32) -> bool {
+        self.chunks.contains_key(&(gx, gy))
+    }
+    pub fn chunk(&self, gx: i32, gy: i32) -> Option<&Chunk> {
+        self.chunks.get(&(gx, gy))
+    }
+    pub fn chunks(&self) -> impl Iterator<Item = ((i32, i32), &Chunk)> + '_ {
+        self.chunks.iter().map(|(coords, chunk)| (*coords, chunk))
+    }
+    // chunk coordinates of a world position
+    pub fn chunk_at(&self, x: f32, y: f32) -> (i32, i32) {
+        let chunk = self.grid_size as f32 * self.scale;
+        ((x / chunk).floor() as i32, (y / chunk).floor() as i32)
+    }
+    pub fn remove_chunk(&mut self, gx: i32, gy: i32) {
+        if self.chunks.remove(&(gx, gy)).is_none() {
+            return;
+        }
+
+        let size = self.grid_size as i32;
+        let (x0, y0) = (gx * size, gy * size);
+        let (x1, y1) = (x0 + size - 1, y0 + size - 1);
+        self.invalidate_meshes(x0, y0, x1, y1);
+        self.invalidate_sdf(x0, y0, x1, y1);
+    }
+    fn get_or_create(&mut self, x: i32, y: i32) -> Option<&mut Chunk> {
+        if !self.chunks.contains_key(&(x, y)) {
+            let chunk = self.create_chunk(x, y);
+            self.chunks.insert((x, y), chunk);
+
+            // neighbours that bridge into the new chunk have been meshed without it
+            let size = self.grid_size as i32;
+            self.invalidate_meshes(x * size, y * size, x * size, y * size);
+        }
+        self.chunks.get_mut(&(x, y))
+    }
+    pub fn save(&self) -> MapSave {
+        let mut chunks: Vec<ChunkSave> = self
+            .chunks
+            .iter()
+            .map(|(coords, chunk)| ChunkSave {
+                x: coords.0,
+                y: coords.1,
+                data: chunk.save(),
+            })
+            .collect();
+        // keep saves stable so level files diff cleanly
+        chunks.sort_by_key(|chunk| (chunk.x, chunk.y));
+
+        MapSave {
+            version: LEVEL_FORMAT_VERSION,
+            grid_size: self.grid_size,
+            scale: self.scale,
+            threshold: self.threshold,
+            chunks,
+        }
+    }
+    pub fn load(&mut self, map: &LevelMap) -> Result<(), LevelError> {
+        match map {
+            LevelMap::Current(save) => self.load_save(save),
+            LevelMap::Legacy(save) => self.load_legacy(save),
+        }
+    }
+    fn load_save(&mut self, save: &MapSave) -> Result<(), LevelError> {
+        if save.version > LEVEL_FORMAT_VERSION {
+            return Err(LevelError::UnsupportedVersion(save.version));
+        }
+        // the header repeats the level's grid params, a level where they disagree
+        // would play differently depending on which one is read
+        if save.grid_size != self.grid_size {
+            return Err(LevelError::InvalidHeader(
+                "map grid_size does not match the level grid",
+            ));
+        }
+        if save.scale != self.scale {
+            return Err(LevelError::InvalidHeader(
+                "map scale does not match the level grid",
+            ));
+        }
+        if save.threshold != self.threshold {
+            return Err(LevelError::InvalidHeader(
+                "map threshold does not match the level grid",
+            ));
+        }
+
+        for chunk in save.chunks.iter() {
+            if let Some(grid) = self.get_or_create(chunk.x, chunk.y) {
+                grid.load(&chunk.data).map_err(|error| LevelError::Chunk {
+                    x: chunk.x,
+                    y: chunk.y,
+                    error,
+                })?;
+            }
+        }
+        Ok(())
+    }
+    fn load_legacy(&mut self, save: &str) -> Result<(), LevelError> {
+        let map = serde_json::from_str::<HashMap<String, String>>(save)?;
+        for (key, data) in map.iter() {
+            let (x, y) = parse_chunk_key(key).ok_or_else(|| LevelError::ChunkKey(key.clone()))?;
+            if let Some(chunk) = self.get_or_create(x, y) {
+                chunk
+                    .load_legacy(data)
+                    .map_err(|error| LevelError::Chunk { x, y, error })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_chunk_key(key: &str) -> Option<(i32, i32)> {
+    let (x, y) = key.split_once(',')?;
+    Some((x.parse().ok()?, y.parse().ok()?))
+}
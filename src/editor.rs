@@ -1,29 +1,90 @@
 #[cfg(not(target_arch = "wasm32"))]
 use arboard::Clipboard;
 
-use bevy::{prelude::*, window::PrimaryWindow};
+use bevy::{ecs::system::SystemParam, prelude::*, window::PrimaryWindow};
 
 use crate::{
-    common::{CurrentLevel, LevelData, MainCamera, State},
+    common::{CurrentLevel, LevelData, MainCamera, State, TimeState},
+    density::DensityMap,
     grid_map::GridMap,
+    level_asset::{LevelAsset, LevelSwap},
+    level_format::{LevelError, LevelMap},
+    level_gen::{TunnelConfig, generate_traversable},
+    recorder::Recorder,
+    records::Records,
+    replay::Replay,
+    sim::{self, Track},
+    storage::SaveStorage,
+    terrain::{GeneratorConfig, GeneratorSettings},
 };
 
+// levels are kept in numbered slots, picked with the number keys
+const USER_LEVEL_SLOTS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+fn user_level_key(slot: usize) -> String {
+    format!("levels/user/{}", slot)
+}
+
+// placing a line takes two clicks, the first end is kept until the second
 enum EndPhase {
     Start,
     End(Vec2),
+    Checkpoint(Vec2),
+}
+
+// the level being edited, along with the recorder that has to hear about every edit
+#[derive(SystemParam)]
+pub struct EditedLevel<'w> {
+    grid_map: ResMut<'w, GridMap>,
+    current_level: ResMut<'w, CurrentLevel>,
+    recorder: ResMut<'w, Recorder>,
+}
+
+// where the mouse points in the world, if it is over the window
+#[derive(SystemParam)]
+pub struct WorldCursor<'w, 's> {
+    window: Single<'w, 's, &'static Window, With<PrimaryWindow>>,
+    camera: Query<'w, 's, (&'static Camera, &'static GlobalTransform), With<MainCamera>>,
+}
+
+impl WorldCursor<'_, '_> {
+    fn position(&self) -> Option<Vec2> {
+        let (camera, camera_transform) = self.camera.single().ok()?;
+        let position = self.window.cursor_position()?;
+        camera.viewport_to_world_2d(camera_transform, position).ok()
+    }
 }
 
 #[derive(Resource)]
 pub struct Editor {
     camera_vel: Vec2,
     end_phase: EndPhase,
+    generator: GeneratorSettings,
+    // the slot P saves to and L loads from
+    slot: usize,
 }
 
 impl Editor {
-    pub fn new() -> Self {
+    // starts from the level's own generator so G rebuilds the same map
+    pub fn new(generator: Option<GeneratorSettings>) -> Self {
         Self {
             camera_vel: Vec2::ZERO,
             end_phase: EndPhase::Start,
+            generator: generator.unwrap_or(GeneratorSettings {
+                seed: 42,
+                config: GeneratorConfig::default(),
+            }),
+            slot: 1,
         }
     }
     pub fn camera_movement(
@@ -65,92 +126,280 @@ impl Editor {
         camera_transform.translation += editor.camera_vel.extend(0.) * time.delta_secs();
     }
 
+    // N picks the next generator, G fills the map from it and T carves a tunnel through it
+    pub fn generate(
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        state: Res<State>,
+        mut editor: ResMut<Editor>,
+        mut level: EditedLevel,
+    ) {
+        if !state.editor {
+            return;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::KeyN) {
+            let presets = GeneratorConfig::presets();
+            let current = presets
+                .iter()
+                .position(|(_, config)| config == &editor.generator.config);
+            let next = current.map_or(0, |i| (i + 1) % presets.len());
+            info!("generator: {}", presets[next].0);
+            editor.generator.config = presets[next].1.clone();
+        }
+
+        if keyboard_input.just_pressed(KeyCode::KeyG) {
+            let generator = editor.generator.build();
+            for x in -4..4 {
+                for y in -4..4 {
+                    level.grid_map.map.generate(x, y, generator.as_ref());
+                }
+            }
+            level.current_level.1.generator = Some(editor.generator.clone());
+            level.recorder.mark_edited();
+        }
+
+        // noise with a tunnel carved from the start to the finish
+        if keyboard_input.just_pressed(KeyCode::KeyT) {
+            let start = Vec2::new(
+                level.current_level.1.start[0],
+                level.current_level.1.start[1],
+            );
+            if let Some(end) = level.current_level.1.end {
+                level.recorder.mark_edited();
+                let generator = editor.generator.build();
+                match generate_traversable(
+                    &mut level.grid_map.map,
+                    generator.as_ref(),
+                    editor.generator.seed,
+                    start,
+                    end,
+                    TunnelConfig::default(),
+                ) {
+                    Ok(attempts) => {
+                        info!("generated a traversable level in {} attempts", attempts);
+                        level.current_level.1.generator = Some(editor.generator.clone());
+                    }
+                    Err(error) => error!("failed to generate level: {}", error),
+                }
+            } else {
+                warn!("place a finish line before generating a level");
+            }
+        }
+    }
     pub fn handle_mouse(
-        mut grid_map: ResMut<GridMap>,
+        mut level: EditedLevel,
         buttons: Res<ButtonInput<MouseButton>>,
-        window: Single<&Window, With<PrimaryWindow>>,
-        q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+        cursor: WorldCursor,
         keyboard_input: Res<ButtonInput<KeyCode>>,
         state: Res<State>,
         time: Res<Time>,
         mut editor: ResMut<Editor>,
-        mut current_level: ResMut<CurrentLevel>,
     ) {
         if !state.editor {
             return;
         }
 
-        let Ok((camera, camera_transform)) = q_camera.single() else {
+        let Some(world_position) = cursor.position() else {
             return;
         };
 
-        let Some(position) = window.cursor_position() else {
-            return;
+        let edited = if keyboard_input.pressed(KeyCode::ShiftLeft) {
+            Editor::modify_start_end(
+                &buttons,
+                &mut editor,
+                &mut level.current_level,
+                &world_position,
+            )
+        } else if keyboard_input.pressed(KeyCode::ControlLeft) {
+            Editor::modify_checkpoints(
+                &buttons,
+                &mut editor,
+                &mut level.current_level,
+                &world_position,
+            )
+        } else {
+            Editor::modify_level(&mut level.grid_map.map, &world_position, &buttons, &time)
         };
-
-        let Ok(world_position) = camera.viewport_to_world_2d(camera_transform, position) else {
+        if edited {
+            level.recorder.mark_edited();
+        }
+    }
+    // P saves the level to the current slot, with the best run as its ghost
+    pub fn save_level(
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        state: Res<State>,
+        editor: Res<Editor>,
+        grid_map: Res<GridMap>,
+        current_level: Res<CurrentLevel>,
+        records: Res<Records>,
+        storage: Res<SaveStorage>,
+    ) {
+        if !state.editor || !keyboard_input.just_pressed(KeyCode::KeyP) {
             return;
+        }
+
+        let mut data = LevelData {
+            level: Some(LevelMap::Current(grid_map.map.save())),
+            start: current_level.1.start,
+            end: current_level.1.end,
+            checkpoints: current_level.1.checkpoints.clone(),
+            grid: Some(grid_map.map.params()),
+            generator: current_level.1.generator.clone(),
+            ghost: None,
         };
 
-        if keyboard_input.just_pressed(KeyCode::KeyG) {
-            for x in -4..4 {
-                for y in -4..4 {
-                    grid_map.generate(x, y, 42, 0.05, (x as f64, y as f64));
+        // the best run so far becomes the author's ghost, pointed at the level as saved,
+        // as long as it still finishes the level with the edits made since
+        let ghost = records
+            .best(current_level.1.content_hash())
+            .and_then(|best| best.replay.clone())
+            .or_else(|| current_level.1.ghost.clone());
+        if let Some(ghost) = ghost {
+            // played on the level as it loads back from the file, densities and all
+            let saved = serde_json::to_vec(&data)
+                .map_err(LevelError::from)
+                .and_then(|save| LevelAsset::from_slice(&save));
+            match saved {
+                Ok(saved) => {
+                    let ghost = Replay {
+                        level_hash: saved.hash,
+                        ..ghost
+                    };
+                    // the hash is the saved level's now, this catches older physics or another ship
+                    match ghost.check(saved.hash) {
+                        Ok(()) if finishes(&saved.map, &saved.data, &ghost) => {
+                            data.ghost = Some(ghost)
+                        }
+                        Ok(()) => {
+                            warn!("the ghost doesn't finish the level as saved, leaving it out")
+                        }
+                        Err(error) => warn!("can't keep the ghost, leaving it out: {}", error),
+                    }
                 }
+                Err(error) => warn!(
+                    "can't load the level as saved, leaving the ghost out: {}",
+                    error
+                ),
             }
         }
 
-        if keyboard_input.pressed(KeyCode::ShiftLeft) {
-            Editor::modify_start_end(&buttons, &mut editor, &mut current_level, &world_position);
-        } else {
-            Editor::modify_level(&mut grid_map, &world_position, &buttons, &time);
+        if let Ok(save) = serde_json::to_string(&data) {
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Ok(clipboard) = &mut Clipboard::new() {
+                clipboard.set_text(&save).ok();
+            }
+            match storage.0.write(&user_level_key(editor.slot), &save) {
+                Ok(()) => info!("saved level to slot {}", editor.slot),
+                Err(error) => warn!("couldn't keep the level: {}", error),
+            }
+            info!(save);
+        }
+    }
+    // picks a slot, swaps in the level saved there with P or deletes it
+    pub fn user_levels(
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        state: Res<State>,
+        storage: Res<SaveStorage>,
+        mut editor: ResMut<Editor>,
+        mut swap: LevelSwap,
+    ) {
+        if !state.editor {
+            return;
         }
 
-        if keyboard_input.just_pressed(KeyCode::KeyP) {
-            let data = LevelData {
-                level: grid_map.save().ok(),
-                start: current_level.1.start,
-                end: current_level.1.end,
-            };
+        if let Some(i) = USER_LEVEL_SLOTS
+            .iter()
+            .position(|key| keyboard_input.just_pressed(*key))
+        {
+            editor.slot = i + 1;
+            match storage.0.read(&user_level_key(editor.slot)) {
+                Some(_) => info!("slot {}, L to load its level", editor.slot),
+                None => info!("slot {}, empty", editor.slot),
+            }
+        }
 
-            if let Ok(save) = serde_json::to_string(&data) {
-                #[cfg(not(target_arch = "wasm32"))]
-                if let Ok(clipboard) = &mut Clipboard::new() {
-                    clipboard.set_text(&save).ok();
-                }
-                
-                info!(save);
+        let key = user_level_key(editor.slot);
+        if keyboard_input.just_pressed(KeyCode::Delete) {
+            match storage.0.remove(&key) {
+                Ok(()) => info!("deleted the level in slot {}", editor.slot),
+                Err(error) => warn!("couldn't delete the level: {}", error),
+            }
+        }
+
+        if !keyboard_input.just_pressed(KeyCode::KeyL) {
+            return;
+        }
+        let Some(save) = storage.0.read(&key) else {
+            warn!("no level saved in slot {}", editor.slot);
+            return;
+        };
+        match LevelAsset::from_slice(save.as_bytes()) {
+            Ok(level) => {
+                swap.swap(&level);
+                info!("loaded the level in slot {}", editor.slot);
             }
+            Err(error) => warn!("couldn't load saved level: {}", error),
         }
     }
 
+    // these return whether the level was changed
     pub fn modify_start_end(
         buttons: &ButtonInput<MouseButton>,
         editor: &mut Editor,
         current_level: &mut CurrentLevel,
         world_position: &Vec2,
-    ) {
+    ) -> bool {
+        let mut edited = false;
         if buttons.just_pressed(MouseButton::Left) {
             current_level.1.start = [world_position.x, world_position.y, 0.];
+            edited = true;
         }
         if buttons.just_pressed(MouseButton::Right) {
             match editor.end_phase {
-                EndPhase::Start => editor.end_phase = EndPhase::End(world_position.clone()),
                 EndPhase::End(start) => {
                     current_level.1.end =
                         Some([[start.x, start.y], [world_position.x, world_position.y]]);
-                    editor.end_phase = EndPhase::Start
+                    editor.end_phase = EndPhase::Start;
+                    edited = true;
                 }
+                _ => editor.end_phase = EndPhase::End(world_position.clone()),
             }
         }
+        edited
+    }
+    // right clicks add a checkpoint after the others, left click removes the last one
+    pub fn modify_checkpoints(
+        buttons: &ButtonInput<MouseButton>,
+        editor: &mut Editor,
+        current_level: &mut CurrentLevel,
+        world_position: &Vec2,
+    ) -> bool {
+        let mut edited = false;
+        if buttons.just_pressed(MouseButton::Left) {
+            edited = current_level.1.checkpoints.pop().is_some();
+        }
+        if buttons.just_pressed(MouseButton::Right) {
+            match editor.end_phase {
+                EndPhase::Checkpoint(start) => {
+                    current_level
+                        .1
+                        .checkpoints
+                        .push([[start.x, start.y], [world_position.x, world_position.y]]);
+                    editor.end_phase = EndPhase::Start;
+                    edited = true;
+                }
+                _ => editor.end_phase = EndPhase::Checkpoint(*world_position),
+            }
+        }
+        edited
     }
     pub fn modify_level(
-        grid_map: &mut GridMap,
+        map: &mut DensityMap,
         world_position: &Vec2,
         buttons: &ButtonInput<MouseButton>,
         time: &Time,
-    ) {
-        let grid_scale = grid_map.scale();
+    ) -> bool {
+        let grid_scale = map.scale();
 
         if buttons.pressed(MouseButton::Left) || buttons.pressed(MouseButton::Right) {
             let rx = (world_position.x / grid_scale).floor() as i32;
@@ -160,7 +409,7 @@ impl Editor {
             let mut vs = Vec::new();
             for x in -range..(range + 1) {
                 for y in -range..(range + 1) {
-                    vs.push(grid_map.get(rx + x, ry + y).unwrap_or(0.));
+                    vs.push(map.get(rx + x, ry + y).unwrap_or(0.));
                 }
             }
 
@@ -176,33 +425,26 @@ impl Editor {
                     if buttons.pressed(MouseButton::Left) {
                         let v = 1. - d / range as f32;
                         if let Some(cv) = vs.get(xu * (range as usize * 2 + 1) + yu) {
-                            grid_map.set(
-                                rx + x,
-                                ry + y,
-                                cv.lerp(v.max(*cv), 30. * time.delta_secs()),
-                            );
+                            map.set(rx + x, ry + y, cv.lerp(v.max(*cv), 30. * time.delta_secs()));
                         }
                     } else if buttons.pressed(MouseButton::Right) {
                         let v = d / range as f32;
                         if let Some(cv) = vs.get(xu * (range as usize * 2 + 1) + yu) {
-                            grid_map.set(
-                                rx + x,
-                                ry + y,
-                                cv.lerp(v.min(*cv), 30. * time.delta_secs()),
-                            );
+                            map.set(rx + x, ry + y, cv.lerp(v.min(*cv), 30. * time.delta_secs()));
                         }
                     }
                 }
             }
+            return true;
         }
+        false
     }
     pub fn render(
         mut gizmos: Gizmos,
         current_level: Res<CurrentLevel>,
         state: Res<State>,
         editor: Res<Editor>,
-        window: Single<&Window, With<PrimaryWindow>>,
-        q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+        cursor: WorldCursor,
     ) {
         if !state.editor {
             return;
@@ -226,21 +468,23 @@ impl Editor {
                     );
                 }
             }
-            EndPhase::End(start) => {
-                let Ok((camera, camera_transform)) = q_camera.single() else {
+            EndPhase::End(start) | EndPhase::Checkpoint(start) => {
+                let Some(world_position) = cursor.position() else {
                     return;
                 };
-
-                let Some(position) = window.cursor_position() else {
-                    return;
+                let color = match editor.end_phase {
+                    EndPhase::Checkpoint(_) => Color::linear_rgba(1., 1., 0., 0.8),
+                    _ => Color::linear_rgba(1., 0., 0., 0.8),
                 };
-
-                let Ok(world_position) = camera.viewport_to_world_2d(camera_transform, position)
-                else {
-                    return;
-                };
-                gizmos.line_2d(start, world_position, Color::linear_rgba(1., 0., 0., 0.8));
+                gizmos.line_2d(start, world_position, color);
             }
         }
     }
 }
+
+// whether replay reaches the finish when played on the level
+fn finishes(map: &DensityMap, level: &LevelData, replay: &Replay) -> bool {
+    sim::simulate(&Track::new(map, level), level.start, &replay.inputs)
+        .last()
+        .is_some_and(|state| matches!(state.time, TimeState::Finished(_)))
+}
@@ -0,0 +1,216 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+use rand::RngExt;
+
+use crate::{
+    common::{CurrentLevel, GameEntity, LevelData, SceneState, State, TimeState},
+    density::DensityMap,
+    grid_map::GridMap,
+    level_format::GridParams,
+    player::Player,
+    terrain::{GeneratorConfig, TerrainGenerator},
+};
+
+// chunks are generated this many chunks around the player and dropped past EVICT_RADIUS
+const STREAM_RADIUS: i32 = 3;
+const EVICT_RADIUS: i32 = 5;
+
+// the corridor runs along +x, wandering up and down, in cells
+const CORRIDOR_HALF_WIDTH: f64 = 9.;
+const CORRIDOR_FADE: f64 = 6.;
+const CORRIDOR_AMPLITUDE: f64 = 40.;
+const CORRIDOR_FREQUENCY: f64 = 0.01;
+
+// world units to a metre on the distance counter, making the ship about eight metres long
+const METRE: f32 = 10.;
+
+#[derive(Resource)]
+pub struct EndlessRun {
+    seed: u32,
+    generator: Corridor,
+    // furthest the player got along the corridor this attempt, and over the session, in metres
+    distance: f32,
+    best: f32,
+}
+
+#[derive(Component)]
+struct DistanceText;
+
+pub struct Endless;
+
+impl Plugin for Endless {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(SceneState::Game),
+            Endless::setup.run_if(resource_exists::<EndlessRun>),
+        )
+        .add_systems(OnExit(SceneState::Game), Endless::cleanup)
+        .add_systems(
+            Update,
+            (stream_chunks, update_distance)
+                .run_if(in_state(SceneState::Game).and(resource_exists::<EndlessRun>)),
+        );
+    }
+}
+
+impl Endless {
+    fn setup(mut commands: Commands) {
+        commands.spawn((
+            Text::new(""),
+            TextFont {
+                font_size: 32.,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.),
+                right: Val::Px(10.),
+                ..default()
+            },
+            DistanceText,
+            GameEntity,
+        ));
+    }
+    fn cleanup(mut commands: Commands) {
+        commands.remove_resource::<EndlessRun>();
+    }
+}
+
+// opens a wandering corridor along +x through another generator's terrain
+struct Corridor {
+    terrain: Box<dyn TerrainGenerator>,
+    path: Perlin,
+}
+
+impl Corridor {
+    fn centre(&self, x: f64) -> f64 {
+        self.path.get([x * CORRIDOR_FREQUENCY, 0.5]) * CORRIDOR_AMPLITUDE
+    }
+    fn open(&self, x: f64, y: f64) -> f64 {
+        let offset = (y - self.centre(x)).abs();
+        ((offset - CORRIDOR_HALF_WIDTH) / CORRIDOR_FADE).clamp(0., 1.)
+    }
+}
+
+impl TerrainGenerator for Corridor {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        self.terrain.sample(x, y) * self.open(x, y)
+    }
+
+    fn fill(&self, x: i32, y: i32, width: u32, height: u32) -> Vec<f64> {
+        let mut vs = self.terrain.fill(x, y, width, height);
+        for (i, v) in vs.iter_mut().enumerate() {
+            let px = x + (i as u32 % width) as i32;
+            let py = y + (i as u32 / width) as i32;
+            *v *= self.open(px as f64, py as f64);
+        }
+        vs
+    }
+}
+
+impl EndlessRun {
+    // the seed also picks which generator the world is built from
+    pub fn new(seed: u32) -> Self {
+        let presets = GeneratorConfig::presets();
+        let (name, config) = &presets[seed as usize % presets.len()];
+        info!("endless run using the {} generator", name);
+
+        Self {
+            seed,
+            generator: Corridor {
+                terrain: config.build(seed),
+                path: Perlin::new(seed.wrapping_add(1)),
+            },
+            distance: 0.,
+            best: 0.,
+        }
+    }
+    // sets up a fresh world with a random seed and the chunks around the start
+    pub fn start(commands: &mut Commands, current_level: &mut CurrentLevel) {
+        let run = EndlessRun::new(rand::rng().random());
+        info!("starting endless run with seed {}", run.seed);
+
+        let params = GridParams::default();
+        let mut map = DensityMap::new(params);
+
+        let start = Vec2::new(0., run.generator.centre(0.) as f32 * params.scale);
+        run.stream(&mut map, start);
+
+        current_level.1 = LevelData {
+            level: None,
+            start: [start.x, start.y, -FRAC_PI_2],
+            end: None,
+            checkpoints: Vec::new(),
+            grid: Some(params),
+            generator: None,
+            ghost: None,
+        };
+
+        commands.insert_resource(GridMap::new(map));
+        commands.insert_resource(run);
+    }
+    // generates every missing chunk within STREAM_RADIUS of position
+    fn stream(&self, map: &mut DensityMap, position: Vec2) {
+        let (cx, cy) = map.chunk_at(position.x, position.y);
+        for gx in (cx - STREAM_RADIUS)..=(cx + STREAM_RADIUS) {
+            for gy in (cy - STREAM_RADIUS)..=(cy + STREAM_RADIUS) {
+                if !map.contains_chunk(gx, gy) {
+                    map.generate(gx, gy, &self.generator);
+                }
+            }
+        }
+        // new chunks read as open space until their distances are in
+        map.update_sdf();
+    }
+}
+
+fn stream_chunks(
+    run: Res<EndlessRun>,
+    mut grid_map: ResMut<GridMap>,
+    query: Query<&Transform, With<Player>>,
+) {
+    let Ok(transform) = query.single() else {
+        return;
+    };
+    let position = transform.translation.xy();
+
+    // meshes of evicted chunks are dropped by grid_map::manage_meshes
+    let map = &mut grid_map.map;
+    run.stream(map, position);
+
+    let (cx, cy) = map.chunk_at(position.x, position.y);
+    let far: Vec<(i32, i32)> = map
+        .chunks()
+        .map(|(coords, _)| coords)
+        .filter(|(gx, gy)| (gx - cx).abs() > EVICT_RADIUS || (gy - cy).abs() > EVICT_RADIUS)
+        .collect();
+    for (gx, gy) in far {
+        map.remove_chunk(gx, gy);
+    }
+}
+
+fn update_distance(
+    mut run: ResMut<EndlessRun>,
+    state: Res<State>,
+    current_level: Res<CurrentLevel>,
+    query: Query<&Transform, With<Player>>,
+    mut text: Single<&mut Text, With<DistanceText>>,
+) {
+    let Ok(transform) = query.single() else {
+        return;
+    };
+
+    // restarting puts the timer back to None, which starts a new attempt
+    if matches!(state.time, TimeState::None) {
+        run.distance = 0.;
+    } else {
+        let travelled = (transform.translation.x - current_level.1.start[0]) / METRE;
+        run.distance = run.distance.max(travelled);
+        run.best = run.best.max(run.distance);
+    }
+
+    ***text = format!("{:.0} m\nbest {:.0} m", run.distance, run.best);
+}
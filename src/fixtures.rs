@@ -0,0 +1,49 @@
+// levels built in code for tests, sizes in cells
+use bevy::prelude::*;
+
+use crate::{
+    common::LevelData,
+    density::DensityMap,
+    level_format::{GridParams, LevelMap},
+};
+
+// solid terrain with a straight corridor along +x from 0 to 64, width across,
+// closed off by a one cell wall at x = wall if there is one
+pub fn corridor(scale: f32, width: i32, wall: Option<i32>) -> DensityMap {
+    let mut map = DensityMap::new(GridParams {
+        scale,
+        ..GridParams::default()
+    });
+    for x in -8..72 {
+        for y in -32..32_i32 {
+            let open = (0..64).contains(&x) && y.abs() <= width / 2 && Some(x) != wall;
+            map.set(x, y, if open { 0. } else { 1. });
+        }
+    }
+    map.update_sdf();
+    map
+}
+
+// far enough from the closed end for the ship on any grid
+pub fn start(scale: f32) -> Vec2 {
+    Vec2::new(16. * scale, 0.)
+}
+
+// across the corridor at x = 60
+pub fn finish(scale: f32) -> [[f32; 2]; 2] {
+    [[60. * scale, -10. * scale], [60. * scale, 10. * scale]]
+}
+
+// the map as a level going from start to finish
+pub fn corridor_level(map: &DensityMap) -> LevelData {
+    let scale = map.scale();
+    LevelData {
+        level: Some(LevelMap::Current(map.save())),
+        start: start(scale).extend(0.).to_array(),
+        end: Some(finish(scale)),
+        checkpoints: Vec::new(),
+        grid: Some(map.params()),
+        generator: None,
+        ghost: None,
+    }
+}
@@ -2,19 +2,26 @@ use bevy::prelude::*;
 
 use crate::common::{
     CurrentLevel, FinishGizmoGroup, GameEntity, MainCamera, MovementGizmoGroup, SceneState, State,
-    TimeState, dist_to_segment,
+    TimeState,
 };
 use crate::editor::Editor;
-use crate::grid_map::{GridMap, manage_meshes};
+use crate::endless::EndlessRun;
+use crate::ghost::{Ghosts, manage_ghost, move_ghost, toggle_ghost};
+use crate::grid_map::{GridMap, manage_meshes, update_sdf};
 use crate::input::{
     InputBuffer, MouseBuffer, clear_buffer, clear_mouse, grab_inputs, grab_mouse,
     handle_cursor_lock, handle_mouse_movement, touch_system,
 };
+use crate::level_asset::{LevelHandle, hot_reload_level};
 use crate::particles::{Particles, ParticlesMaterial};
-use crate::player::{CursorMove, Player};
+use crate::player::Player;
+use crate::recorder::{Playback, Recorder, play_input, record_input, replay_keys};
+use crate::records::{flush_records, save_records, track_runs};
 use crate::render::{
     configure_gizmos, draw_dots, render_finish, render_movement, update_gizmo_config,
 };
+use crate::settings::{Settings, SettingsStore};
+use crate::sim::TICK;
 
 #[derive(Component)]
 struct TimeText;
@@ -24,20 +31,23 @@ pub struct Game;
 impl Plugin for Game {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(SceneState::Game), (Game::setup, configure_gizmos))
-            .add_systems(OnExit(SceneState::Game), Game::cleanup)
+            .add_systems(OnExit(SceneState::Game), (Game::cleanup, flush_records))
             .init_gizmo_group::<MovementGizmoGroup>()
             .init_gizmo_group::<FinishGizmoGroup>()
             .add_systems(
                 FixedUpdate,
                 (
                     touch_system,
-                    handle_mouse_movement,
+                    handle_mouse_movement.run_if(not(resource_exists::<Playback>)),
+                    play_input.run_if(resource_exists::<Playback>),
+                    // endless worlds are new every time, there are no runs there worth keeping
+                    record_input.run_if(not(resource_exists::<EndlessRun>)),
                     //
-                    manage_time,
-                    //
-                    Player::movement,
-                    Player::apply_velocity,
-                    check_finish,
+                    update_sdf,
+                    Player::step,
+                    (track_runs, move_ghost)
+                        .chain()
+                        .run_if(not(resource_exists::<EndlessRun>)),
                     //
                     clear_buffer,
                     clear_mouse,
@@ -55,11 +65,26 @@ impl Plugin for Game {
                     // input
                     handle_cursor_lock,
                     Editor::camera_movement,
-                    Editor::handle_mouse,
+                    (
+                        Editor::generate,
+                        Editor::handle_mouse,
+                        Editor::save_level,
+                        Editor::user_levels,
+                    )
+                        .chain(),
                     state_management,
+                    (
+                        replay_keys.run_if(not(resource_exists::<EndlessRun>)),
+                        save_records,
+                    ),
+                    (toggle_ghost, manage_ghost)
+                        .chain()
+                        .run_if(not(resource_exists::<EndlessRun>)),
                     // particles
+                    Player::emit_particles,
                     Particles::update,
                     // render
+                    hot_reload_level,
                     manage_meshes,
                     draw_dots,
                     update_time_text,
@@ -80,17 +105,12 @@ impl Game {
         mut commands: Commands,
         asset_server: Res<AssetServer>,
         current_level: Res<CurrentLevel>,
+        settings: Res<Settings>,
         mut meshes: ResMut<Assets<Mesh>>,
         mut materials: ResMut<Assets<ParticlesMaterial>>,
     ) {
-        let mut grid_map = GridMap::new(10., 16, 0.5, true);
-
         commands.spawn((Camera2d::default(), MainCamera, GameEntity));
 
-        if let Some(level) = &current_level.1.level {
-            grid_map.load(level);
-        }
-
         Player::spawn(
             current_level.1.start[0],
             current_level.1.start[1],
@@ -119,14 +139,19 @@ impl Game {
         ));
 
         commands.insert_resource(particles);
-        commands.insert_resource(grid_map);
         commands.insert_resource(State {
-            debug: false,
+            debug: settings.debug,
             editor: false,
             time: TimeState::None,
-            follow: 1.
+            follow: 1.,
+            checkpoint: 0,
+            splits: Vec::new(),
+            best_splits: Vec::new(),
         });
-        commands.insert_resource(Editor::new());
+        commands.insert_resource(Editor::new(current_level.1.generator.clone()));
+        let level_hash = current_level.1.content_hash();
+        commands.insert_resource(Recorder::new(level_hash));
+        commands.insert_resource(Ghosts::new(level_hash, settings.ghost));
         commands.insert_resource(TimeState::None);
         commands.init_resource::<InputBuffer>();
         commands.init_resource::<MouseBuffer>();
@@ -137,8 +162,12 @@ impl Game {
         }
 
         commands.remove_resource::<GridMap>();
+        commands.remove_resource::<LevelHandle>();
         commands.remove_resource::<State>();
         commands.remove_resource::<Editor>();
+        commands.remove_resource::<Recorder>();
+        commands.remove_resource::<Playback>();
+        commands.remove_resource::<Ghosts>();
         commands.remove_resource::<Particles>();
         commands.remove_resource::<TimeState>();
         commands.remove_resource::<InputBuffer>();
@@ -155,64 +184,6 @@ fn manage_exit(
     }
 }
 
-fn check_finish(
-    query: Query<&Transform, With<Player>>,
-    current_level: Res<CurrentLevel>,
-    mut state: ResMut<State>,
-) {
-    if !matches!(state.time, TimeState::Timing(_)) {
-        return;
-    }
-
-    let Ok(transform) = query.single() else {
-        return;
-    };
-
-    let Some(end) = current_level.1.end else {
-        return;
-    };
-
-    let mut distance: Option<f32> = None;
-
-    let end1 = Vec2::from_array(end[0]);
-    let end2 = Vec2::from_array(end[1]);
-
-    for point in Player::get_points(transform) {
-        let d = dist_to_segment(point, end1, end2);
-        distance = Some(match distance {
-            Some(v) => v.min(d),
-            None => d,
-        })
-    }
-
-    let Some(distance) = distance else {
-        return;
-    };
-
-    if distance < 50.
-        && let TimeState::Timing(time) = state.time
-    {
-        state.time = TimeState::Finished(time);
-    }
-}
-
-fn manage_time(mut state: ResMut<State>, query: Query<&CursorMove, With<Player>>, time: Res<Time>) {
-    match state.time {
-        TimeState::None => {
-            let Ok(cursor_move) = query.single() else {
-                return;
-            };
-            if cursor_move.0 != Vec2::ZERO {
-                state.time = TimeState::Timing(0.);
-            }
-        }
-        TimeState::Timing(current_time) => {
-            state.time = TimeState::Timing(current_time + time.delta_secs())
-        }
-        TimeState::Finished(_) => (),
-    };
-}
-
 fn state_management(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut state: ResMut<State>,
@@ -220,9 +191,11 @@ fn state_management(
     mut commands: Commands,
     current_level: Res<CurrentLevel>,
     asset_server: Res<AssetServer>,
+    mut settings: SettingsStore,
 ) {
     if keyboard_input.just_pressed(KeyCode::Semicolon) {
         state.debug = !state.debug;
+        settings.update(|settings| settings.debug = state.debug);
     }
 
     if keyboard_input.just_pressed(KeyCode::KeyR)
@@ -246,12 +219,21 @@ fn update_time_text(state: Res<State>, mut query: Query<&mut Text, With<TimeText
     if state.is_changed()
         && let Ok(mut text) = query.single_mut()
     {
-        **text = format!(
-            "{:.2}",
-            match state.time {
-                TimeState::None => 0.,
-                TimeState::Timing(time) | TimeState::Finished(time) => time,
-            }
-        );
+        let mut lines = vec![format!("{:.2}", state.time.seconds())];
+        for (i, split) in state.splits.iter().enumerate() {
+            let time = *split as f32 * TICK;
+            lines.push(match state.best_splits.get(i) {
+                Some(best) => {
+                    format!(
+                        "{}. {:.2} ({:+.2})",
+                        i + 1,
+                        time,
+                        time - *best as f32 * TICK
+                    )
+                }
+                None => format!("{}. {:.2}", i + 1, time),
+            });
+        }
+        **text = lines.join("\n");
     }
 }
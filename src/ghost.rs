@@ -0,0 +1,183 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_transform_interpolation::prelude::TransformInterpolation;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::{CurrentLevel, GameEntity, State, TimeState},
+    grid_map::GridMap,
+    player::Player,
+    records::Records,
+    replay::Replay,
+    settings::SettingsStore,
+    sim::{self, Track},
+};
+
+const GHOST_COLOR: Color = Color::srgba(1., 1., 1., 0.35);
+
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum GhostSource {
+    #[default]
+    PersonalBest,
+    Author,
+}
+
+#[derive(Resource)]
+pub struct Ghosts {
+    level_hash: u64,
+    source: GhostSource,
+    // rebuilt before the next attempt after the best run or source changes
+    dirty: bool,
+    run: Option<GhostRun>,
+}
+
+// where the ghost is after every tick of its run, and the tick its timer started on
+struct GhostRun {
+    start: Transform,
+    path: Vec<Transform>,
+    first_tick: usize,
+}
+
+#[derive(Component)]
+pub struct Ghost;
+
+impl Ghosts {
+    pub fn new(level_hash: u64, source: GhostSource) -> Self {
+        Self {
+            level_hash,
+            source,
+            dirty: true,
+            run: None,
+        }
+    }
+    // shows the new best from the next attempt on, if the best is what's being shown
+    pub fn best_changed(&mut self) {
+        if self.source == GhostSource::PersonalBest {
+            self.dirty = true;
+        }
+    }
+    fn replay<'a>(
+        &self,
+        records: &'a Records,
+        current_level: &'a CurrentLevel,
+    ) -> Option<&'a Replay> {
+        match self.source {
+            GhostSource::PersonalBest => records
+                .best(self.level_hash)
+                .and_then(|best| best.replay.as_ref()),
+            GhostSource::Author => current_level.1.ghost.as_ref(),
+        }
+    }
+}
+
+impl GhostRun {
+    fn new(replay: &Replay, track: &Track, start: [f32; 3]) -> Option<Self> {
+        let states = sim::simulate(track, start, &replay.inputs);
+        let first_tick = states
+            .iter()
+            .position(|state| !matches!(state.time, TimeState::None))?;
+        Some(Self {
+            start: sim::ship_transform(start[0], start[1], start[2]),
+            path: states.iter().map(|state| state.transform).collect(),
+            first_tick,
+        })
+    }
+    // the ghost's position when the player's timer shows time
+    fn at(&self, time: TimeState) -> Transform {
+        let mut transform = match time {
+            TimeState::None => self.start,
+            TimeState::Timing(ticks) | TimeState::Finished(ticks) => {
+                let tick = (self.first_tick + ticks as usize).min(self.path.len() - 1);
+                self.path[tick]
+            }
+        };
+        // under the player
+        transform.translation.z = 0.5;
+        transform
+    }
+}
+
+pub fn toggle_ghost(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut ghosts: ResMut<Ghosts>,
+    mut settings: SettingsStore,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyH) {
+        ghosts.source = match ghosts.source {
+            GhostSource::PersonalBest => GhostSource::Author,
+            GhostSource::Author => GhostSource::PersonalBest,
+        };
+        ghosts.dirty = true;
+        settings.update(|settings| settings.ghost = ghosts.source);
+
+        info!(
+            "ghost: {}",
+            match ghosts.source {
+                GhostSource::PersonalBest => "personal best",
+                GhostSource::Author => "author",
+            }
+        );
+    }
+}
+
+// what the ghost's run is picked from and played out on
+#[derive(SystemParam)]
+pub struct GhostLevel<'w> {
+    records: Res<'w, Records>,
+    current_level: Res<'w, CurrentLevel>,
+    grid_map: Res<'w, GridMap>,
+}
+
+// turns the chosen run into a path between attempts, spawning or removing the ghost to match
+pub fn manage_ghost(
+    mut ghosts: ResMut<Ghosts>,
+    level: GhostLevel,
+    state: Res<State>,
+    query: Query<Entity, With<Ghost>>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    if !ghosts.dirty || matches!(state.time, TimeState::Timing(_)) {
+        return;
+    }
+    ghosts.dirty = false;
+
+    let track = Track::new(&level.grid_map.map, &level.current_level.1);
+    let run = ghosts
+        .replay(&level.records, &level.current_level)
+        .and_then(|replay| match replay.check(ghosts.level_hash) {
+            Ok(()) => GhostRun::new(replay, &track, level.current_level.1.start),
+            Err(error) => {
+                warn!("can't show ghost: {}", error);
+                None
+            }
+        });
+
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    if let Some(run) = &run {
+        commands
+            .spawn((
+                Ghost,
+                run.at(state.time),
+                Visibility::default(),
+                TransformInterpolation,
+                GameEntity,
+            ))
+            .with_children(|parent| Player::spawn_sprites(parent, &asset_server, GHOST_COLOR));
+    }
+    ghosts.run = run;
+}
+
+pub fn move_ghost(
+    ghosts: Res<Ghosts>,
+    state: Res<State>,
+    mut query: Query<&mut Transform, With<Ghost>>,
+) {
+    let Some(run) = &ghosts.run else {
+        return;
+    };
+    for mut transform in &mut query {
+        *transform = run.at(state.time);
+    }
+}
@@ -1,283 +1,217 @@
-use std::num::ParseIntError;
-
-use bevy::{platform::collections::HashMap, prelude::*};
+use bevy::{
+    platform::collections::HashMap,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, futures::check_ready},
+};
 
 use crate::{
-    common::div_floor,
+    common::{MainCamera, viewport_rect},
+    density::{Chunk, DensityMap},
     grid::{Grid, GridMaterial},
+    terrain_mesh::{MeshAttributes, MeshSnapshot},
 };
 
+// distance from the camera view, in chunks, within which meshes are built and beyond which they are freed
+const MESH_LOAD_MARGIN: f32 = 1.;
+const MESH_UNLOAD_MARGIN: f32 = 2.;
+
+// the level's terrain along with the meshes drawn for it
 #[derive(Resource)]
 pub struct GridMap {
-    scale: f32,
-    grid_size: u32,
+    pub map: DensityMap,
     grids: HashMap<(i32, i32), Grid>,
-    threshold: f32,
-    smooth: bool,
+    tasks: MeshTasks,
 }
 
+// remeshes running in the background, keyed by chunk
+#[derive(Default)]
+struct MeshTasks(HashMap<(i32, i32), Task<MeshAttributes>>);
+
 impl GridMap {
-    pub fn new(scale: f32, grid_size: u32, threshold: f32, smooth: bool) -> Self {
+    pub fn new(map: DensityMap) -> Self {
         Self {
-            scale,
-            grid_size,
+            map,
             grids: HashMap::new(),
-            threshold,
-            smooth,
-        }
-    }
-    pub fn scale(&self) -> f32 {
-        self.scale
-    }
-    fn create_grid(&self, x: i32, y: i32) -> Grid {
-        Grid::new(
-            x as f32 * self.scale * self.grid_size as f32,
-            y as f32 * self.scale * self.grid_size as f32,
-            self.grid_size,
-            self.grid_size,
-            self.scale,
-        )
-    }
-    pub fn set(&mut self, x: i32, y: i32, v: f32) {
-        let gx = div_floor(x, self.grid_size as i32);
-        let gy = div_floor(y, self.grid_size as i32);
-
-        let x = (x - gx * self.grid_size as i32) as u32;
-        let y = (y - gy * self.grid_size as i32) as u32;
-
-        let grid = match self.grids.get_mut(&(gx, gy)) {
-            Some(grid) => Some(grid),
-            None => {
-                let grid = self.create_grid(gx, gy);
-                self.grids.insert((gx, gy), grid);
-                self.grids.get_mut(&(gx, gy))
-            }
-        };
-        let Some(grid) = grid else {
-            return;
-        };
-
-        grid.set(x, y, v);
-    }
-    pub fn get(&self, x: i32, y: i32) -> Option<f32> {
-        let gx = div_floor(x, self.grid_size as i32);
-        let gy = div_floor(y, self.grid_size as i32);
-
-        let x = (x - gx * self.grid_size as i32) as u32;
-        let y = (y - gy * self.grid_size as i32) as u32;
-
-        match self.grids.get(&(gx, gy)) {
-            Some(grid) => grid.get(x, y),
-            None => None,
+            tasks: MeshTasks::default(),
         }
     }
-    fn gets(&self, x: f32, y: f32) -> Option<f32> {
-        let x0 = x.floor() as i32;
-        let y0 = y.floor() as i32;
-        let x1 = x0 + 1;
-        let y1 = y0 + 1;
-
-        let tx = x.fract();
-        let ty = y.fract();
-
-        let v00 = self.get(x0, y0)?;
-        let v10 = self.get(x1, y0)?;
-        let v01 = self.get(x0, y1)?;
-        let v11 = self.get(x1, y1)?;
-
-        let v = (v00 * (1. - tx) + v10 * tx) * (1. - ty) + (v01 * (1. - tx) + v11 * tx) * ty;
-
-        return Some(v);
-    }
-    pub fn get_world(&self, x: f32, y: f32) -> Option<f32> {
-        let gx = x / self.scale;
-        let gy = y / self.scale;
-        self.gets(gx, gy)
-    }
-    //
-    fn get_normal(&self, x: f32, y: f32) -> Option<(f32, f32)> {
-        let x0 = x.floor() as i32;
-        let y0 = y.floor() as i32;
-        let x1 = x0 + 1;
-        let y1 = y0 + 1;
-
-        let tx = x - x0 as f32;
-        let ty = y - y0 as f32;
-
-        let grad = |gx: i32, gy: i32| -> Option<(f32, f32)> {
-            let dx = self.get(gx + 1, gy)? - self.get(gx - 1, gy)?;
-            let dy = self.get(gx, gy + 1)? - self.get(gx, gy - 1)?;
-            Some((dx, dy))
-        };
-
-        let (g00x, g00y) = grad(x0, y0)?;
-        let (g10x, g10y) = grad(x1, y0)?;
-        let (g01x, g01y) = grad(x0, y1)?;
-        let (g11x, g11y) = grad(x1, y1)?;
-
-        let mut nx =
-            (g00x * (1. - tx) + g10x * tx) * (1. - ty) + (g01x * (1. - tx) + g11x * tx) * ty;
-        let mut ny =
-            (g00y * (1. - tx) + g10y * tx) * (1. - ty) + (g01y * (1. - tx) + g11y * tx) * ty;
-
-        let nl = (nx.powi(2) + ny.powi(2)).sqrt();
-        if nl > 0. {
-            nx /= nl;
-            ny /= nl;
-        }
-
-        return Some((nx, ny));
-    }
-    pub fn get_normal_world(&self, x: f32, y: f32) -> Option<(f32, f32)> {
-        let gx = x / self.scale;
-        let gy = y / self.scale;
-        self.get_normal(gx, gy)
-    }
-    //
     pub fn manage_meshes(
         &mut self,
         mut commands: &mut Commands,
         mut meshes: &mut ResMut<Assets<Mesh>>,
         mut materials: &mut ResMut<Assets<GridMaterial>>,
+        view: Option<Rect>,
     ) {
-        let mut to_mesh = Vec::new();
-        for (coords, grid) in self.grids.iter() {
-            if grid.mesh.is_none() {
-                let bridges = Some([
-                    self.grids.get(&(coords.0 + 1, coords.1 + 1)),
-                    self.grids.get(&(coords.0 + 1, coords.1)),
-                    self.grids.get(&(coords.0, coords.1 + 1)),
-                ]);
-
-                to_mesh.push((
-                    true,
-                    coords.clone(),
-                    grid.gen_attributes(self.threshold, self.smooth, &bridges),
-                ));
-            } else if grid.changed {
-                let bridges = Some([
-                    self.grids.get(&(coords.0 + 1, coords.1 + 1)),
-                    self.grids.get(&(coords.0 + 1, coords.1)),
-                    self.grids.get(&(coords.0, coords.1 + 1)),
-                ]);
-
-                to_mesh.push((
-                    false,
-                    coords.clone(),
-                    grid.gen_attributes(self.threshold, self.smooth, &bridges),
-                ));
+        self.map.update_sdf();
+
+        // chunks dropped from the map take their meshes with them
+        let map = &self.map;
+        self.tasks
+            .0
+            .retain(|coords, _| map.contains_chunk(coords.0, coords.1));
+        self.grids.retain(|coords, grid| {
+            let keep = map.contains_chunk(coords.0, coords.1);
+            if !keep {
+                grid.despawn(commands);
             }
-        }
-        for (new, coords, attributes) in to_mesh.into_iter() {
+            keep
+        });
+
+        // only chunks near the camera keep a mesh, with a wider margin for
+        // unloading so chunks on the edge don't flicker in and out
+        let chunk = self.map.grid_size() as f32 * self.map.scale();
+        let in_range = |chunk_rect: Rect, margin: f32| {
+            view.is_none_or(|view| {
+                !view
+                    .inflate(margin * chunk)
+                    .intersect(chunk_rect)
+                    .is_empty()
+            })
+        };
+
+        let to_unload: Vec<(i32, i32)> = self
+            .map
+            .chunks()
+            .filter(|(coords, chunk)| {
+                !in_range(chunk.rect(), MESH_UNLOAD_MARGIN)
+                    && (self
+                        .grids
+                        .get(coords)
+                        .is_some_and(|grid| grid.mesh.is_some())
+                        || self.tasks.0.contains_key(coords))
+            })
+            .map(|(coords, _)| coords)
+            .collect();
+        for coords in to_unload {
+            self.tasks.0.remove(&coords);
             if let Some(grid) = self.grids.get_mut(&coords) {
-                if new {
-                    grid.spawn_attributes(&mut commands, &mut meshes, &mut materials, attributes);
-                } else {
-                    grid.changed = false;
-                    grid.set_mesh(&mut meshes, attributes);
-                }
+                grid.unload(commands);
             }
         }
-    }
 
-    pub fn draw_dots(
-        &self,
-        gizmos: &mut Gizmos,
-        camera: &Camera,
-        camera_transform: &GlobalTransform,
-    ) {
-        for (_coords, grid) in self.grids.iter() {
-            if grid.in_viewport(camera, camera_transform) {
-                grid.draw_dots(gizmos);
+        // results are applied even when the chunk has changed again since, it
+        // remeshes from here next
+        let mut ready = Vec::new();
+        self.tasks.0.retain(|coords, task| match check_ready(task) {
+            Some(attributes) => {
+                ready.push((*coords, attributes));
+                false
+            }
+            None => true,
+        });
+
+        let mut chunks = 0;
+        let mut vertices = 0;
+        let mut unmerged = 0;
+        for (coords, attributes) in ready {
+            let Some(grid) = self.grids.get_mut(&coords) else {
+                continue;
+            };
+
+            chunks += 1;
+            vertices += attributes.vertex_count();
+            unmerged += attributes.unmerged_vertices;
+
+            if grid.mesh.is_none() {
+                grid.spawn_attributes(&mut commands, &mut meshes, &mut materials, attributes);
+            } else {
+                grid.swap_mesh(&mut commands, &mut meshes, attributes);
             }
         }
+
+        // one remesh per chunk at a time, a chunk edited while its remesh runs
+        // starts another once that one is in
+        let to_mesh: Vec<(i32, i32)> = self
+            .map
+            .chunks()
+            .filter(|(coords, chunk)| {
+                in_range(chunk.rect(), MESH_LOAD_MARGIN)
+                    && !self.tasks.0.contains_key(coords)
+                    && self
+                        .grids
+                        .get(coords)
+                        .is_none_or(|grid| grid.revision != Some(chunk.revision))
+            })
+            .map(|(coords, _)| coords)
+            .collect();
+
+        let pool = AsyncComputeTaskPool::get();
+        for coords in to_mesh {
+            let Some(chunk) = self.map.chunk(coords.0, coords.1) else {
+                continue;
+            };
+            let grid = self.grids.entry(coords).or_insert_with(|| Grid::new(chunk));
+            grid.revision = Some(chunk.revision);
+
+            // the task works on its own copies so editing can carry on meanwhile
+            let Some(snapshot) = MeshSnapshot::new(&self.map, coords.0, coords.1) else {
+                continue;
+            };
+            let (threshold, smooth) = (self.map.threshold(), self.map.smooth());
+            let task = pool.spawn(async move { snapshot.gen_attributes(threshold, smooth) });
+            self.tasks.0.insert(coords, task);
+        }
+
+        if chunks > 0 {
+            debug!(
+                "meshed {} chunks with {} vertices ({} without sharing and merging)",
+                chunks, vertices, unmerged
+            );
+        }
     }
 
-    pub fn draw_segments(
-        &self,
-        gizmos: &mut Gizmos,
-        camera: &Camera,
-        camera_transform: &GlobalTransform,
-    ) {
-        for (_coords, grid) in self.grids.iter() {
-            if grid.in_viewport(camera, camera_transform) {
-                grid.draw_segments(self.threshold, self.smooth, gizmos);
-            }
+    pub fn despawn(&self, commands: &mut Commands) {
+        for grid in self.grids.values() {
+            grid.despawn(commands);
         }
     }
-    pub fn draw_borders(
-        &self,
-        gizmos: &mut Gizmos,
-        camera: &Camera,
-        camera_transform: &GlobalTransform,
-    ) {
-        for (coords, grid) in self.grids.iter() {
-            if grid.in_viewport(camera, camera_transform) {
-                gizmos.rect_2d(
-                    Isometry2d::from_translation(
-                        (Vec2::new(coords.0 as f32, coords.1 as f32) + 0.5)
-                            * self.grid_size as f32
-                            * self.scale,
-                    ),
-                    Vec2::splat(self.grid_size as f32 * self.scale),
-                    Color::linear_rgb(0., 1., 0.),
-                );
+
+    // chunks of the map that overlap the view, or all of them without one
+    fn visible(&self, view: Option<Rect>) -> impl Iterator<Item = ((i32, i32), &Chunk)> + '_ {
+        self.map.chunks().filter(move |(_, chunk)| {
+            view.is_none_or(|view| !view.intersect(chunk.rect()).is_empty())
+        })
+    }
+
+    pub fn draw_dots(&self, gizmos: &mut Gizmos, view: Option<Rect>) {
+        for (_coords, chunk) in self.visible(view) {
+            let origin = chunk.rect().min;
+            let spacing = chunk.spacing();
+            for x in 0..chunk.width {
+                for y in 0..chunk.height {
+                    if let Some(v) = chunk.get(x, y) {
+                        let color = Color::linear_rgba(1., 1., 1., v.powf(3.));
+                        gizmos.circle_2d(
+                            origin + Vec2::new(x as f32, y as f32) * spacing,
+                            spacing / 5.,
+                            color,
+                        );
+                    }
+                }
             }
         }
     }
-    pub fn generate(&mut self, gx: i32, gy: i32, seed: u32, scale: f64, offset: (f64, f64)) {
-        let grid = match self.grids.get_mut(&(gx, gy)) {
-            Some(grid) => Some(grid),
-            None => {
-                let grid = self.create_grid(gx, gy);
-                self.grids.insert((gx, gy), grid);
-                self.grids.get_mut(&(gx, gy))
-            }
-        };
-        let Some(grid) = grid else {
-            return;
-        };
 
-        grid.generate(
-            seed,
-            scale,
-            (
-                offset.0 * self.grid_size as f64 * scale,
-                offset.1 * self.grid_size as f64 * scale,
-            ),
-        );
-    }
-    pub fn save(&self) -> Result<String, serde_json::Error> {
-        let mut ready = HashMap::new();
-        for (coords, grid) in self.grids.iter() {
-            ready.insert(format!("{},{}", coords.0, coords.1), grid.save());
+    // outlines of the chunks in view, ones that leave it are cut off at its chunks
+    pub fn draw_segments(&self, gizmos: &mut Gizmos, view: Option<Rect>) {
+        let visible = self.visible(view).map(|(coords, _)| coords);
+        for contour in self.map.contours_of(visible, None) {
+            let color = match contour.closed {
+                true => Color::linear_rgb(1., 1., 1.),
+                false => Color::linear_rgb(1., 0.5, 0.),
+            };
+            let first = contour.closed.then(|| contour.points[0]);
+            gizmos.linestrip_2d(contour.points.into_iter().chain(first), color);
         }
-        serde_json::to_string(&ready)
     }
-    pub fn load(&mut self, save: &str) {
-        let Ok(map) = serde_json::from_str::<HashMap<String, String>>(save) else {
-            return;
-        };
-        for (coords, data) in map.iter() {
-            let coords: Vec<Result<i32, ParseIntError>> =
-                coords.split(',').map(|s| s.parse::<i32>()).collect();
-            let Some(Ok(x)) = coords.get(0).cloned() else {
-                continue;
-            };
-            let Some(Ok(y)) = coords.get(1).cloned() else {
-                continue;
-            };
-            let Some(grid) = (match self.grids.get_mut(&(x, y)) {
-                Some(grid) => Some(grid),
-                None => {
-                    let grid = self.create_grid(x, y);
-                    self.grids.insert((x, y), grid);
-                    self.grids.get_mut(&(x, y))
-                }
-            }) else {
-                continue;
-            };
-            grid.load(data);
+    pub fn draw_borders(&self, gizmos: &mut Gizmos, view: Option<Rect>) {
+        for (_coords, chunk) in self.visible(view) {
+            let rect = chunk.rect();
+            gizmos.rect_2d(
+                Isometry2d::from_translation(rect.center()),
+                rect.size(),
+                Color::linear_rgb(0., 1., 0.),
+            );
         }
     }
 }
@@ -287,6 +221,16 @@ pub fn manage_meshes(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<GridMaterial>>,
     mut grid_map: ResMut<GridMap>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
 ) {
-    grid_map.manage_meshes(&mut commands, &mut meshes, &mut materials);
+    let view = camera_query
+        .single()
+        .ok()
+        .and_then(|(camera, camera_transform)| viewport_rect(camera, camera_transform));
+    grid_map.manage_meshes(&mut commands, &mut meshes, &mut materials, view);
+}
+
+// brings distances up to date with edits made since the last tick, before the physics reads them
+pub fn update_sdf(mut grid_map: ResMut<GridMap>) {
+    grid_map.map.update_sdf();
 }
@@ -9,6 +9,7 @@ use bevy::{
 use crate::{
     common::{MainCamera, State, TimeState},
     player::{CursorMove, Player},
+    sim::TICK,
 };
 
 pub fn touch_system(
@@ -57,7 +58,6 @@ pub fn handle_mouse_movement(
     cursor_options: Single<&CursorOptions>,
     mouse_buffer: Res<MouseBuffer>,
     mut query: Query<&mut CursorMove, With<Player>>,
-    time: Res<Time>,
     state: Res<State>,
 ) {
     if state.editor || matches!(state.time, TimeState::Finished(_)) {
@@ -75,7 +75,7 @@ pub fn handle_mouse_movement(
 
     if delta != Vec2::ZERO {
         for mut cursor_move in &mut query {
-            cursor_move.0 += Vec2::new(delta.x, -delta.y) / 2. * time.delta_secs();
+            cursor_move.0 += Vec2::new(delta.x, -delta.y) / 2. * TICK;
             if cursor_move.0.length() > 1. {
                 cursor_move.0 = cursor_move.0.normalize();
             }
@@ -0,0 +1,111 @@
+use bevy::{asset::AssetLoader, prelude::*};
+
+#[cfg(feature = "game")]
+use bevy::ecs::system::SystemParam;
+
+#[cfg(feature = "game")]
+use crate::{
+    common::CurrentLevel, ghost::Ghosts, grid_map::GridMap, recorder::Recorder, settings::Settings,
+};
+use crate::{common::LevelData, density::DensityMap, level_format::LevelError};
+
+#[derive(Asset, TypePath)]
+pub struct LevelAsset {
+    pub data: LevelData,
+    pub map: DensityMap,
+    pub hash: u64,
+}
+
+impl LevelAsset {
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, LevelError> {
+        let mut data = serde_json::from_slice::<LevelData>(bytes)?;
+
+        let params = data.grid_params();
+        params.validate()?;
+        data.grid = Some(params);
+
+        let mut map = DensityMap::new(params);
+        if let Some(level) = &data.level {
+            map.load(level)?;
+        }
+        map.update_sdf();
+
+        let hash = data.content_hash();
+        Ok(Self { data, map, hash })
+    }
+}
+
+#[derive(Resource)]
+pub struct LevelHandle(pub Handle<LevelAsset>);
+
+#[derive(Default, TypePath)]
+pub struct LevelAssetLoader;
+
+impl AssetLoader for LevelAssetLoader {
+    type Asset = LevelAsset;
+    type Settings = ();
+    type Error = LevelError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut bevy::asset::LoadContext<'_>,
+    ) -> Result<LevelAsset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        LevelAsset::from_slice(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.json"]
+    }
+}
+
+// what replacing the running level touches, runs on the old one don't carry over
+#[cfg(feature = "game")]
+#[derive(SystemParam)]
+pub struct LevelSwap<'w, 's> {
+    grid_map: ResMut<'w, GridMap>,
+    pub current_level: ResMut<'w, CurrentLevel>,
+    settings: Res<'w, Settings>,
+    commands: Commands<'w, 's>,
+}
+
+#[cfg(feature = "game")]
+impl LevelSwap<'_, '_> {
+    pub fn swap(&mut self, level: &LevelAsset) {
+        self.grid_map.despawn(&mut self.commands);
+        *self.grid_map = GridMap::new(level.map.clone());
+        self.current_level.1 = level.data.clone();
+        self.commands.insert_resource(Recorder::new(level.hash));
+        self.commands
+            .insert_resource(Ghosts::new(level.hash, self.settings.ghost));
+    }
+}
+
+// rebuilds the running level when its file changes on disk
+#[cfg(feature = "game")]
+pub fn hot_reload_level(
+    mut events: MessageReader<AssetEvent<LevelAsset>>,
+    handle: Option<Res<LevelHandle>>,
+    levels: Res<Assets<LevelAsset>>,
+    mut swap: LevelSwap,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+
+    for event in events.read() {
+        if !event.is_modified(&handle.0) {
+            continue;
+        }
+        let Some(level) = levels.get(&handle.0) else {
+            continue;
+        };
+
+        swap.swap(level);
+
+        info!("reloaded level {}", swap.current_level.0 + 1);
+    }
+}
@@ -0,0 +1,211 @@
+// the checks speedwing-validate runs on level files, usable without a window
+
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::{
+    common::LevelData,
+    density::DensityMap,
+    level_asset::LevelAsset,
+    level_format::{LevelMap, check_cells, decode_cells, decode_legacy_cells},
+    level_gen::reachable,
+    sim,
+};
+
+// everything wrong with a level file, empty if it's fine
+pub fn check_level(bytes: &[u8]) -> Vec<String> {
+    // go through the chunks one by one first so every broken one gets reported
+    let problems = match serde_json::from_slice::<LevelData>(bytes) {
+        Ok(data) => check_chunks(&data),
+        Err(error) => return vec![error.to_string()],
+    };
+    if !problems.is_empty() {
+        return problems;
+    }
+
+    match LevelAsset::from_slice(bytes) {
+        Ok(level) => check_layout(&level.data, &level.map),
+        Err(error) => vec![error.to_string()],
+    }
+}
+
+pub fn check_chunks(data: &LevelData) -> Vec<String> {
+    let params = data.grid_params();
+    let expected = (params.grid_size * params.grid_size) as usize;
+
+    let chunks: Vec<(String, Result<Vec<f32>, String>)> = match &data.level {
+        None => Vec::new(),
+        Some(LevelMap::Current(save)) => save
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let cells = decode_cells(&chunk.data).map_err(|error| error.to_string());
+                (format!("{},{}", chunk.x, chunk.y), cells)
+            })
+            .collect(),
+        Some(LevelMap::Legacy(save)) => match serde_json::from_str::<HashMap<String, String>>(save)
+        {
+            Ok(map) => map
+                .iter()
+                .map(|(key, data)| {
+                    let cells = decode_legacy_cells(data).map_err(|error| error.to_string());
+                    (key.clone(), cells)
+                })
+                .collect(),
+            Err(error) => return vec![error.to_string()],
+        },
+    };
+
+    let mut problems = Vec::new();
+    for (key, cells) in chunks {
+        match cells.and_then(|cells| check_cells(&cells, expected).map_err(|e| e.to_string())) {
+            Ok(()) => (),
+            Err(error) => problems.push(format!("chunk {}: {}", key, error)),
+        }
+    }
+    problems.sort();
+    problems
+}
+
+pub fn check_layout(data: &LevelData, map: &DensityMap) -> Vec<String> {
+    let mut problems = Vec::new();
+    let start = Vec2::new(data.start[0], data.start[1]);
+
+    let transform = sim::ship_transform(data.start[0], data.start[1], data.start[2]);
+    let inside = |point: Vec2| {
+        map.get_distance_world(point.x, point.y)
+            .is_some_and(|d| d < 0.)
+    };
+    if inside(start) {
+        problems.push("start is inside terrain".to_string());
+    } else if sim::hull_points(&transform).into_iter().any(inside) {
+        problems.push("ship overlaps terrain at the start".to_string());
+    }
+
+    // lines are usually drawn from wall to wall, so the ends resting in the
+    // terrain are fine as long as the part between them is open
+    let crosses_terrain = |line: [[f32; 2]; 2]| {
+        let (a, b) = (Vec2::from_array(line[0]), Vec2::from_array(line[1]));
+        let length = a.distance(b);
+        let steps = (length / (map.scale() * 0.5)).ceil().max(1.) as u32;
+        (0..=steps)
+            .map(|i| i as f32 / steps as f32)
+            .filter(|t| (t * length).min((1. - t) * length) > map.scale())
+            .any(|t| inside(a.lerp(b, t)))
+    };
+    for (i, checkpoint) in data.checkpoints.iter().enumerate() {
+        if crosses_terrain(*checkpoint) {
+            problems.push(format!("checkpoint {} crosses terrain", i + 1));
+        }
+        if !reachable(map, start, *checkpoint) {
+            problems.push(format!(
+                "checkpoint {} is unreachable from the start",
+                i + 1
+            ));
+        }
+    }
+
+    let Some(end) = data.end else {
+        problems.push("finish line is missing".to_string());
+        return problems;
+    };
+
+    if crosses_terrain(end) {
+        problems.push("finish line crosses terrain".to_string());
+    }
+
+    if !reachable(map, start, end) {
+        problems.push("finish is unreachable from the start".to_string());
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+
+    use super::*;
+    use crate::{
+        fixtures::{corridor, corridor_level},
+        level_format::{ChunkSave, MapSave, encode_cells},
+    };
+
+    fn layout(wall: Option<i32>, edit: fn(&mut LevelData)) -> Vec<String> {
+        let map = corridor(5., 24, wall);
+        let mut data = corridor_level(&map);
+        edit(&mut data);
+        check_layout(&data, &map)
+    }
+
+    #[test]
+    fn open_level_passes_on_a_fine_grid() {
+        assert_eq!(layout(None, |_| ()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn walled_off_finish_fails_on_a_fine_grid() {
+        assert_eq!(
+            layout(Some(30), |_| ()),
+            vec!["finish is unreachable from the start".to_string()]
+        );
+    }
+
+    #[test]
+    fn finish_from_wall_to_wall_passes() {
+        let problems = layout(None, |data| {
+            data.end = Some([[300., -65.], [300., 65.]]);
+        });
+        assert_eq!(problems, Vec::<String>::new());
+    }
+
+    #[test]
+    fn finish_through_a_wall_crosses_terrain() {
+        let problems = layout(Some(60), |_| ());
+        assert!(problems.contains(&"finish line crosses terrain".to_string()));
+    }
+
+    #[test]
+    fn start_inside_terrain_fails() {
+        let problems = layout(None, |data| data.start = [40., 100., 0.]);
+        assert_eq!(problems[0], "start is inside terrain");
+    }
+
+    #[test]
+    fn missing_finish_fails() {
+        let problems = layout(None, |data| data.end = None);
+        assert_eq!(problems, vec!["finish line is missing".to_string()]);
+    }
+
+    #[test]
+    fn chunk_with_the_wrong_cell_count_fails() {
+        let map = corridor(5., 24, None);
+        let mut data = corridor_level(&map);
+        data.level = Some(LevelMap::Current(MapSave {
+            chunks: vec![ChunkSave {
+                x: 0,
+                y: 0,
+                data: encode_cells(&[0.5; 10]),
+            }],
+            ..map.save()
+        }));
+        assert_eq!(
+            check_chunks(&data),
+            vec!["chunk 0,0: expected 256 cells, found 10".to_string()]
+        );
+    }
+
+    #[test]
+    fn chunk_with_nan_densities_fails() {
+        let mut cells = vec![0.5_f32; 256];
+        cells[3] = f32::NAN;
+        let bytes: Vec<u8> = cells.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let chunks = HashMap::from([("0,0".to_string(), STANDARD.encode(bytes))]);
+
+        let mut data = corridor_level(&corridor(5., 24, None));
+        data.level = Some(LevelMap::Legacy(serde_json::to_string(&chunks).unwrap()));
+        assert_eq!(
+            check_chunks(&data),
+            vec!["chunk 0,0: non-finite value in cell 3".to_string()]
+        );
+    }
+}
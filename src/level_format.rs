@@ -0,0 +1,242 @@
+use std::fmt;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+// bump this whenever the layout of `MapSave` or the chunk encoding changes
+pub const LEVEL_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MapSave {
+    pub version: u32,
+    pub grid_size: u32,
+    pub scale: f32,
+    pub threshold: f32,
+    pub chunks: Vec<ChunkSave>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChunkSave {
+    pub x: i32,
+    pub y: i32,
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct GridParams {
+    pub scale: f32,
+    pub grid_size: u32,
+    pub threshold: f32,
+    pub smooth: bool,
+}
+
+impl Default for GridParams {
+    fn default() -> Self {
+        Self {
+            scale: 10.,
+            grid_size: 16,
+            threshold: 0.5,
+            smooth: true,
+        }
+    }
+}
+
+impl GridParams {
+    pub fn validate(&self) -> Result<(), LevelError> {
+        if self.grid_size < 2 {
+            return Err(LevelError::InvalidHeader("grid_size must be at least 2"));
+        }
+        if !(self.scale.is_finite() && self.scale > 0.) {
+            return Err(LevelError::InvalidHeader(
+                "scale must be finite and above zero",
+            ));
+        }
+        if !(self.threshold > 0. && self.threshold < 1.) {
+            return Err(LevelError::InvalidHeader(
+                "threshold must be between zero and one",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// old levels store the map as a json string of "x,y" -> base64 f32 chunks
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum LevelMap {
+    Current(MapSave),
+    Legacy(String),
+}
+
+#[derive(Debug)]
+pub enum CellError {
+    Base64(base64::DecodeError),
+    Corrupt,
+    CellCount { expected: usize, found: usize },
+    NonFinite(usize),
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellError::Base64(error) => write!(f, "bad base64: {}", error),
+            CellError::Corrupt => write!(f, "corrupt cell data"),
+            CellError::CellCount { expected, found } => {
+                write!(f, "expected {} cells, found {}", expected, found)
+            }
+            CellError::NonFinite(index) => write!(f, "non-finite value in cell {}", index),
+        }
+    }
+}
+
+impl std::error::Error for CellError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CellError::Base64(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    InvalidHeader(&'static str),
+    ChunkKey(String),
+    Chunk { x: i32, y: i32, error: CellError },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::Io(error) => write!(f, "failed to read level: {}", error),
+            LevelError::Json(error) => write!(f, "bad level json: {}", error),
+            LevelError::UnsupportedVersion(version) => write!(
+                f,
+                "level format version {} is newer than supported version {}",
+                version, LEVEL_FORMAT_VERSION
+            ),
+            LevelError::InvalidHeader(reason) => write!(f, "invalid level header: {}", reason),
+            LevelError::ChunkKey(key) => write!(f, "bad chunk key \"{}\"", key),
+            LevelError::Chunk { x, y, error } => write!(f, "chunk {},{}: {}", x, y, error),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LevelError::Io(error) => Some(error),
+            LevelError::Json(error) => Some(error),
+            LevelError::Chunk { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LevelError {
+    fn from(error: std::io::Error) -> Self {
+        LevelError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for LevelError {
+    fn from(error: serde_json::Error) -> Self {
+        LevelError::Json(error)
+    }
+}
+
+fn quantize(v: f32) -> u8 {
+    (v.clamp(0., 1.) * 255.).round() as u8
+}
+
+fn dequantize(v: u8) -> f32 {
+    v as f32 / 255.
+}
+
+// packbits style: a header below 128 is followed by header + 1 literal bytes,
+// a header of 128 or above repeats the next byte header - 126 times
+fn rle_encode(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let mut run = 1;
+        while i + run < bytes.len() && bytes[i + run] == bytes[i] && run < 129 {
+            run += 1;
+        }
+
+        if run >= 2 {
+            out.push((run + 126) as u8);
+            out.push(bytes[i]);
+            i += run;
+        } else {
+            let start = i;
+            i += 1;
+            while i < bytes.len()
+                && i - start < 128
+                && !(i + 1 < bytes.len() && bytes[i] == bytes[i + 1])
+            {
+                i += 1;
+            }
+            out.push((i - start - 1) as u8);
+            out.extend_from_slice(&bytes[start..i]);
+        }
+    }
+    out
+}
+
+fn rle_decode(bytes: &[u8]) -> Result<Vec<u8>, CellError> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let header = bytes[i] as usize;
+        i += 1;
+        if header < 128 {
+            out.extend_from_slice(bytes.get(i..i + header + 1).ok_or(CellError::Corrupt)?);
+            i += header + 1;
+        } else {
+            let v = *bytes.get(i).ok_or(CellError::Corrupt)?;
+            out.extend(std::iter::repeat_n(v, header - 126));
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+pub fn encode_cells(data: &[f32]) -> String {
+    let bytes: Vec<u8> = data.iter().map(|v| quantize(*v)).collect();
+    STANDARD.encode(rle_encode(&bytes))
+}
+
+pub fn decode_cells(save: &str) -> Result<Vec<f32>, CellError> {
+    let bytes = STANDARD.decode(save).map_err(CellError::Base64)?;
+    Ok(rle_decode(&bytes)?.into_iter().map(dequantize).collect())
+}
+
+// a chunk needs exactly expected cells, all of them finite
+pub fn check_cells(data: &[f32], expected: usize) -> Result<(), CellError> {
+    if data.len() != expected {
+        return Err(CellError::CellCount {
+            expected,
+            found: data.len(),
+        });
+    }
+    if let Some(index) = data.iter().position(|v| !v.is_finite()) {
+        return Err(CellError::NonFinite(index));
+    }
+    Ok(())
+}
+
+pub fn decode_legacy_cells(save: &str) -> Result<Vec<f32>, CellError> {
+    let bytes = STANDARD.decode(save).map_err(CellError::Base64)?;
+    if bytes.len() % 4 != 0 {
+        return Err(CellError::Corrupt);
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
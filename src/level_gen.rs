@@ -0,0 +1,277 @@
+use std::{collections::VecDeque, fmt};
+
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+use noise::{NoiseFn, Perlin};
+
+use crate::{
+    common::{FINISH_DISTANCE, dist_to_segment},
+    density::{DensityMap, SDF_BAND},
+    sim,
+    terrain::TerrainGenerator,
+};
+
+// chunks generated around the start and finish
+const MARGIN: i32 = 2;
+
+// sizes in cells
+#[derive(Clone, Copy)]
+pub struct TunnelConfig {
+    pub radius: f32,
+    // how far the tunnel wanders from the straight line, and over what length
+    pub meander: f32,
+    pub wavelength: f32,
+    pub attempts: u32,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        Self {
+            radius: 6.,
+            meander: 20.,
+            wavelength: 60.,
+            attempts: 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GenerationError {
+    pub attempts: u32,
+}
+
+impl fmt::Display for GenerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "finish still unreachable after {} attempts",
+            self.attempts
+        )
+    }
+}
+
+impl std::error::Error for GenerationError {}
+
+// fills the area around start and end from generator and carves a tunnel
+// between them, widening it until the player can get from one to the other.
+// returns how many carving passes it took
+pub fn generate_traversable(
+    map: &mut DensityMap,
+    generator: &dyn TerrainGenerator,
+    seed: u32,
+    start: Vec2,
+    end: [[f32; 2]; 2],
+    config: TunnelConfig,
+) -> Result<u32, GenerationError> {
+    let finish = (Vec2::from_array(end[0]) + Vec2::from_array(end[1])) / 2.;
+
+    let (ax, ay) = map.chunk_at(start.x.min(finish.x), start.y.min(finish.y));
+    let (bx, by) = map.chunk_at(start.x.max(finish.x), start.y.max(finish.y));
+    for gx in (ax - MARGIN)..=(bx + MARGIN) {
+        for gy in (ay - MARGIN)..=(by + MARGIN) {
+            map.generate(gx, gy, generator);
+        }
+    }
+
+    let scale = map.scale();
+    let clearance = sim::hull_radius() / scale;
+
+    for attempt in 0..config.attempts {
+        // later passes wander differently and cut wider, adding to what is already open
+        let path = Perlin::new(seed.wrapping_add(attempt));
+        let radius = config.radius.max(clearance + 1.) + attempt as f32;
+        carve(map, &path, start / scale, finish / scale, radius, config);
+
+        map.update_sdf();
+        if reachable(map, start, end) {
+            return Ok(attempt + 1);
+        }
+    }
+
+    Err(GenerationError {
+        attempts: config.attempts,
+    })
+}
+
+// lowers the density along a meandering path from a to b, in cells
+fn carve(map: &mut DensityMap, path: &Perlin, a: Vec2, b: Vec2, radius: f32, config: TunnelConfig) {
+    let length = a.distance(b);
+    let dir = (b - a).normalize_or(Vec2::X);
+    let side = dir.perp();
+    let fade = 2.;
+    let reach = (radius + fade).ceil() as i32;
+
+    // how much of each point's density to keep
+    let mut keep: HashMap<(i32, i32), f32> = HashMap::new();
+    let steps = (length * 2.).ceil().max(1.) as u32;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        // the offset tapers off so the ends stay on the start and finish
+        let wander = path.get([(t * length / config.wavelength) as f64, 0.5]) as f32
+            * config.meander
+            * (t * std::f32::consts::PI).sin();
+        let centre = a.lerp(b, t) + side * wander;
+
+        let (cx, cy) = (centre.x.round() as i32, centre.y.round() as i32);
+        for x in (cx - reach)..=(cx + reach) {
+            for y in (cy - reach)..=(cy + reach) {
+                let d = Vec2::new(x as f32, y as f32).distance(centre);
+                let k = ((d - radius) / fade).clamp(0., 1.);
+                let entry = keep.entry((x, y)).or_insert(1.);
+                *entry = entry.min(k);
+            }
+        }
+    }
+
+    for ((x, y), k) in keep {
+        if let Some(v) = map.get(x, y) {
+            map.set(x, y, v * k);
+        }
+    }
+}
+
+// room around a point in cells, up to reach. distances are only kept within
+// SDF_BAND of the terrain, so further out it's measured through the nearest
+// points that have one
+fn room(map: &DensityMap, x: i32, y: i32, reach: f32) -> Option<f32> {
+    let d = map.get_sdf(x, y)?;
+    if d < SDF_BAND {
+        return Some(d);
+    }
+
+    let r = reach.ceil() as i32;
+    let mut room = reach;
+    for qx in (x - r)..=(x + r) {
+        for qy in (y - r)..=(y + r) {
+            let step = Vec2::new((qx - x) as f32, (qy - y) as f32).length();
+            if step >= room {
+                continue;
+            }
+            if let Some(d) = map.get_sdf(qx, qy).filter(|d| *d < SDF_BAND) {
+                room = room.min(step + d);
+            }
+        }
+    }
+    Some(room)
+}
+
+// flood fills the points with room for the ship from the start, looking for the finish
+pub fn reachable(map: &DensityMap, start: Vec2, end: [[f32; 2]; 2]) -> bool {
+    let scale = map.scale();
+    let clearance = sim::hull_radius() / scale;
+    let end = (Vec2::from_array(end[0]), Vec2::from_array(end[1]));
+    let open = |x: i32, y: i32| room(map, x, y, clearance).is_some_and(|d| d >= clearance);
+
+    let first = (
+        (start.x / scale).round() as i32,
+        (start.y / scale).round() as i32,
+    );
+    if map.get_sdf(first.0, first.1).is_none_or(|d| d < 0.) {
+        return false;
+    }
+
+    // the start can sit closer to a wall than that, the ship only has to get
+    // clear of it from there
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    let reach = clearance.ceil() as i32;
+    for x in (first.0 - reach)..=(first.0 + reach) {
+        for y in (first.1 - reach)..=(first.1 + reach) {
+            let step = Vec2::new((x - first.0) as f32, (y - first.1) as f32).length();
+            if step <= clearance && open(x, y) && seen.insert((x, y)) {
+                queue.push_back((x, y));
+            }
+        }
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        let point = Vec2::new(x as f32, y as f32) * scale;
+        if dist_to_segment(point, end.0, end.1) < FINISH_DISTANCE {
+            return true;
+        }
+
+        for next in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+            if open(next.0, next.1) && seen.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{corridor, finish, start},
+        level_format::GridParams,
+    };
+
+    struct Solid;
+
+    impl TerrainGenerator for Solid {
+        fn sample(&self, _x: f64, _y: f64) -> f64 {
+            1.
+        }
+    }
+
+    #[test]
+    fn wide_corridor_is_reachable_on_a_fine_grid() {
+        let scale = 5.;
+        let map = corridor(scale, 24, None);
+        assert!(reachable(&map, start(scale), finish(scale)));
+    }
+
+    #[test]
+    fn narrow_corridor_is_unreachable_on_a_fine_grid() {
+        let scale = 5.;
+        let map = corridor(scale, 4, None);
+        assert!(!reachable(&map, start(scale), finish(scale)));
+    }
+
+    #[test]
+    fn wide_corridor_is_reachable_on_the_default_grid() {
+        let scale = GridParams::default().scale;
+        let map = corridor(scale, 24, None);
+        assert!(reachable(&map, start(scale), finish(scale)));
+    }
+
+    #[test]
+    fn start_close_to_a_wall_is_reachable() {
+        let scale = GridParams::default().scale;
+        let map = corridor(scale, 24, None);
+        let start = start(scale) + Vec2::Y * 8. * scale;
+        assert!(reachable(&map, start, finish(scale)));
+    }
+
+    #[test]
+    fn narrow_corridor_is_unreachable_on_the_default_grid() {
+        // 90 units across, less than the hull's 100
+        let scale = GridParams::default().scale;
+        let map = corridor(scale, 8, None);
+        assert!(!reachable(&map, start(scale), finish(scale)));
+    }
+
+    #[test]
+    fn generates_through_solid_terrain_on_a_fine_grid() {
+        let scale = 5.;
+        let mut map = DensityMap::new(GridParams {
+            scale,
+            ..GridParams::default()
+        });
+        let end = [[600., -50.], [600., 50.]];
+        let result = generate_traversable(
+            &mut map,
+            &Solid,
+            1,
+            Vec2::ZERO,
+            end,
+            TunnelConfig::default(),
+        );
+        assert!(result.is_ok());
+        assert!(reachable(&map, Vec2::ZERO, end));
+    }
+}
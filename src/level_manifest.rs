@@ -0,0 +1,66 @@
+use std::fmt;
+
+use bevy::{asset::AssetLoader, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::level_format::LevelError;
+
+pub const MANIFEST_PATH: &str = "levels/levels.manifest.json";
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difficulty::Easy => write!(f, "Easy"),
+            Difficulty::Medium => write!(f, "Medium"),
+            Difficulty::Hard => write!(f, "Hard"),
+            Difficulty::Expert => write!(f, "Expert"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LevelInfo {
+    pub id: String,
+    pub name: String,
+    pub author: String,
+    pub difficulty: Difficulty,
+    pub path: String,
+}
+
+#[derive(Asset, TypePath, Serialize, Deserialize)]
+pub struct LevelManifest {
+    pub levels: Vec<LevelInfo>,
+}
+
+#[derive(Default, TypePath)]
+pub struct LevelManifestLoader;
+
+impl AssetLoader for LevelManifestLoader {
+    type Asset = LevelManifest;
+    type Settings = ();
+    type Error = LevelError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut bevy::asset::LoadContext<'_>,
+    ) -> Result<LevelManifest, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(serde_json::from_slice::<LevelManifest>(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["manifest.json"]
+    }
+}
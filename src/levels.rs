@@ -1,21 +1,55 @@
 use bevy::{
+    asset::LoadState,
+    ecs::system::SystemParam,
     prelude::*,
     window::{CursorGrabMode, CursorOptions},
 };
 
 use crate::{
-    common::{CurrentLevel, LevelData, SceneState},
-    text_asset::TextAsset,
+    common::{ButtonQuery, CurrentLevel, SceneState},
+    grid_map::GridMap,
+    level_asset::{LevelAsset, LevelHandle},
+    level_manifest::{LevelManifest, MANIFEST_PATH},
+    records::Records,
+    sim::TICK,
 };
 
+const LEVELS_PER_PAGE: usize = 12;
+
 #[derive(Component)]
 struct LevelEntity;
 
 #[derive(Component)]
-struct Level(u32);
+struct LevelErrorText;
+
+#[derive(Component)]
+struct LevelGrid;
+
+#[derive(Component)]
+struct PageText;
+
+// filled in with the best time once the level has loaded
+#[derive(Component)]
+struct BestTime(Handle<LevelAsset>);
+
+#[derive(Component)]
+struct PageButton(i32);
+
+#[derive(Component)]
+struct Level {
+    index: u32,
+    path: String,
+}
 
 #[derive(Resource)]
-struct TextHandle(Handle<TextAsset>);
+struct ManifestHandle(Handle<LevelManifest>);
+
+#[derive(Resource)]
+struct LevelPage {
+    page: usize,
+    pages: usize,
+    dirty: bool,
+}
 
 pub struct Levels;
 
@@ -25,15 +59,29 @@ impl Plugin for Levels {
             .add_systems(OnExit(SceneState::Levels), Levels::cleanup)
             .add_systems(
                 Update,
-                (handle_level_buttons, check_load).run_if(in_state(SceneState::Levels)),
+                (
+                    build_level_grid,
+                    handle_page_buttons,
+                    handle_level_buttons,
+                    update_best_times,
+                    check_load,
+                )
+                    .run_if(in_state(SceneState::Levels)),
             );
     }
 }
 
 impl Levels {
-    fn setup(mut commands: Commands) {
+    fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         commands.spawn((Camera2d, LevelEntity));
 
+        commands.insert_resource(ManifestHandle(asset_server.load(MANIFEST_PATH)));
+        commands.insert_resource(LevelPage {
+            page: 0,
+            pages: 1,
+            dirty: true,
+        });
+
         commands
             .spawn((
                 Node {
@@ -56,42 +104,46 @@ impl Levels {
                     },
                 ));
 
-                parent
-                    .spawn(Node {
+                parent.spawn((
+                    Node {
                         display: Display::Grid,
-                        grid_template_columns: RepeatedGridTrack::flex(3, 1.),
-                        grid_template_rows: RepeatedGridTrack::flex(1, 1.),
+                        grid_template_columns: RepeatedGridTrack::flex(4, 1.),
                         row_gap: Val::Px(10.),
                         column_gap: Val::Px(10.),
                         ..default()
+                    },
+                    LevelGrid,
+                ));
+
+                parent
+                    .spawn(Node {
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(20.),
+                        ..default()
                     })
-                    .with_children(|grid| {
-                        for i in 0..3 {
-                            grid.spawn((
-                                Button,
-                                Node {
-                                    width: Val::Px(60.),
-                                    height: Val::Px(60.),
-                                    align_items: AlignItems::Center,
-                                    justify_content: JustifyContent::Center,
-                                    border_radius: BorderRadius::all(Val::Px(10.)),
-                                    ..default()
-                                },
-                                BackgroundColor(Color::srgb(0., 0.4, 0.8)),
-                                Level(i),
-                            ))
-                            .with_children(|parent| {
-                                parent.spawn((
-                                    Text::new(format!("{}", i + 1)),
-                                    TextFont {
-                                        font_size: 32.,
-                                        ..default()
-                                    },
-                                ));
-                            });
-                        }
+                    .with_children(|parent| {
+                        spawn_page_button(parent, "<", -1);
+                        parent.spawn((
+                            Text::new(""),
+                            TextFont {
+                                font_size: 24.,
+                                ..default()
+                            },
+                            PageText,
+                        ));
+                        spawn_page_button(parent, ">", 1);
                     });
 
+                parent.spawn((
+                    Text::new(""),
+                    TextFont {
+                        font_size: 24.,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(1., 0.3, 0.3)),
+                    LevelErrorText,
+                ));
+
                 // parent
                 //     .spawn((
                 //         Button,
@@ -123,15 +175,179 @@ impl Levels {
             commands.entity(entity).despawn();
         }
 
-        commands.remove_resource::<TextHandle>();
+        commands.remove_resource::<ManifestHandle>();
+        commands.remove_resource::<LevelPage>();
+    }
+}
+
+fn spawn_page_button(parent: &mut ChildSpawnerCommands, label: &str, direction: i32) {
+    parent
+        .spawn((
+            Button,
+            Node {
+                width: Val::Px(50.),
+                height: Val::Px(50.),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                border_radius: BorderRadius::all(Val::Px(10.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0., 0.4, 0.8)),
+            PageButton(direction),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(label),
+                TextFont {
+                    font_size: 32.,
+                    ..default()
+                },
+            ));
+        });
+}
+
+// the manifest being loaded and its changes
+#[derive(SystemParam)]
+struct LevelList<'w, 's> {
+    handle: Option<Res<'w, ManifestHandle>>,
+    manifests: Res<'w, Assets<LevelManifest>>,
+    events: MessageReader<'w, 's, AssetEvent<LevelManifest>>,
+}
+
+fn build_level_grid(
+    mut commands: Commands,
+    mut list: LevelList,
+    asset_server: Res<AssetServer>,
+    mut page: ResMut<LevelPage>,
+    grid: Single<Entity, With<LevelGrid>>,
+    mut page_text: Single<&mut Text, (With<PageText>, Without<LevelErrorText>)>,
+    mut error_text: Single<&mut Text, (With<LevelErrorText>, Without<PageText>)>,
+) {
+    let Some(handle) = list.handle else {
+        return;
+    };
+
+    if list.events.read().any(|event| event.is_modified(&handle.0)) {
+        page.dirty = true;
+    }
+
+    if !page.dirty {
+        return;
+    }
+
+    let Some(manifest) = list.manifests.get(&handle.0) else {
+        if let LoadState::Failed(error) = asset_server.load_state(&handle.0) {
+            page.dirty = false;
+            error!("failed to load level manifest: {}", error);
+            ***error_text = format!("Failed to load level list: {}", error);
+        }
+        return;
+    };
+
+    page.dirty = false;
+    page.pages = manifest.levels.len().div_ceil(LEVELS_PER_PAGE).max(1);
+    page.page = page.page.min(page.pages - 1);
+    ***page_text = format!("{} / {}", page.page + 1, page.pages);
+
+    commands
+        .entity(*grid)
+        .despawn_children()
+        .with_children(|grid| {
+            for (i, info) in manifest
+                .levels
+                .iter()
+                .enumerate()
+                .skip(page.page * LEVELS_PER_PAGE)
+                .take(LEVELS_PER_PAGE)
+            {
+                grid.spawn((
+                    Button,
+                    Node {
+                        width: Val::Px(160.),
+                        height: Val::Px(80.),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        flex_direction: FlexDirection::Column,
+                        border_radius: BorderRadius::all(Val::Px(10.)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgb(0., 0.4, 0.8)),
+                    Level {
+                        index: i as u32,
+                        path: info.path.clone(),
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::new(format!("{}. {}", i + 1, info.name)),
+                        TextFont {
+                            font_size: 24.,
+                            ..default()
+                        },
+                    ));
+                    parent.spawn((
+                        Text::new(format!("{} - {}", info.difficulty, info.author)),
+                        TextFont {
+                            font_size: 14.,
+                            ..default()
+                        },
+                        TextColor(Color::srgba(1., 1., 1., 0.7)),
+                    ));
+                    parent.spawn((
+                        Text::new(""),
+                        TextFont {
+                            font_size: 14.,
+                            ..default()
+                        },
+                        TextColor(Color::srgba(1., 1., 1., 0.7)),
+                        BestTime(asset_server.load(info.path.clone())),
+                    ));
+                });
+            }
+        });
+}
+
+fn update_best_times(
+    mut query: Query<(&mut Text, &BestTime)>,
+    levels: Res<Assets<LevelAsset>>,
+    records: Res<Records>,
+) {
+    for (mut text, best_time) in &mut query {
+        let Some(level) = levels.get(&best_time.0) else {
+            continue;
+        };
+        let best = match records.best(level.hash) {
+            Some(best) => format!("Best {:.2}", best.ticks as f32 * TICK),
+            None => "No time yet".to_string(),
+        };
+        if text.0 != best {
+            text.0 = best;
+        }
+    }
+}
+
+fn handle_page_buttons(mut query: ButtonQuery<PageButton>, mut page: ResMut<LevelPage>) {
+    for (interaction, mut bg, button) in &mut query {
+        match interaction {
+            Interaction::Pressed => {
+                let next = (page.page as i32 + button.0).clamp(0, page.pages as i32 - 1) as usize;
+                if next != page.page {
+                    page.page = next;
+                    page.dirty = true;
+                }
+            }
+            Interaction::Hovered => {
+                *bg = BackgroundColor(Color::srgb(0., 0.5, 0.9));
+            }
+            Interaction::None => {
+                *bg = BackgroundColor(Color::srgb(0., 0.4, 0.8));
+            }
+        }
     }
 }
 
 fn handle_level_buttons(
-    mut query: Query<
-        (&Interaction, &mut BackgroundColor, &Level),
-        (Changed<Interaction>, With<Level>),
-    >,
+    mut query: ButtonQuery<Level>,
     // mut next_state: ResMut<NextState<SceneState>>,
     mut cursor_options: Single<&mut CursorOptions>,
     mut current_level: ResMut<CurrentLevel>,
@@ -143,9 +359,9 @@ fn handle_level_buttons(
     for (interaction, mut bg, level) in &mut query {
         match interaction {
             Interaction::Pressed => {
-                current_level.0 = level.0;
-                let handle = asset_server.load(format!("levels/{}.txt", level.0));
-                commands.insert_resource(TextHandle(handle));
+                current_level.0 = level.index;
+                let handle = asset_server.load(level.path.clone());
+                commands.insert_resource(LevelHandle(handle));
                 // next_state.set(SceneState::Game);
             }
             Interaction::Hovered => {
@@ -160,23 +376,27 @@ fn handle_level_buttons(
 
 fn check_load(
     mut current_level: ResMut<CurrentLevel>,
-    handle: Option<Res<TextHandle>>,
-    text_assets: Res<Assets<TextAsset>>,
+    handle: Option<Res<LevelHandle>>,
+    levels: Res<Assets<LevelAsset>>,
+    asset_server: Res<AssetServer>,
     mut next_state: ResMut<NextState<SceneState>>,
+    mut error_text: Query<&mut Text, With<LevelErrorText>>,
+    mut commands: Commands,
 ) {
     let Some(handle) = handle else {
         return;
     };
-    if let Some(text) = text_assets.get(&handle.0) {
-        if let Ok(data) = serde_json::from_str::<LevelData>(&text.0) {
-            current_level.1 = data
-        } else {
-            current_level.1 = LevelData {
-                level: None,
-                start: [0., 0., 0.],
-                end: None
-            }
-        }
+
+    if let Some(level) = levels.get(&handle.0) {
+        current_level.1 = level.data.clone();
+        commands.insert_resource(GridMap::new(level.map.clone()));
         next_state.set(SceneState::Game);
+    } else if let LoadState::Failed(error) = asset_server.load_state(&handle.0) {
+        commands.remove_resource::<LevelHandle>();
+
+        error!("failed to load level {}: {}", current_level.0 + 1, error);
+        if let Ok(mut text) = error_text.single_mut() {
+            **text = format!("Failed to load level {}: {}", current_level.0 + 1, error);
+        }
     }
 }
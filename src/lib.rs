@@ -0,0 +1,53 @@
+pub mod common;
+pub mod contour;
+pub mod density;
+pub mod level_asset;
+pub mod level_check;
+pub mod level_format;
+pub mod level_gen;
+pub mod level_manifest;
+pub mod replay;
+pub mod sim;
+pub mod storage;
+pub mod terrain;
+pub mod terrain_mesh;
+
+pub mod raycast;
+
+#[cfg(test)]
+mod fixtures;
+
+// everything that draws, takes input or runs systems, left out of headless
+// builds such as the level validator's
+#[cfg(feature = "game")]
+pub mod editor;
+#[cfg(feature = "game")]
+pub mod endless;
+#[cfg(feature = "game")]
+pub mod ghost;
+#[cfg(feature = "game")]
+pub mod grid;
+#[cfg(feature = "game")]
+pub mod grid_map;
+#[cfg(feature = "game")]
+pub mod input;
+#[cfg(feature = "game")]
+pub mod player;
+#[cfg(feature = "game")]
+pub mod recorder;
+#[cfg(feature = "game")]
+pub mod records;
+#[cfg(feature = "game")]
+pub mod render;
+#[cfg(feature = "game")]
+pub mod settings;
+
+#[cfg(feature = "game")]
+pub mod game;
+#[cfg(feature = "game")]
+pub mod levels;
+#[cfg(feature = "game")]
+pub mod menu;
+
+#[cfg(feature = "game")]
+pub mod particles;
@@ -1,36 +1,30 @@
-mod common;
-mod editor;
-mod grid;
-mod grid_map;
-mod input;
-mod player;
-mod render;
-
-mod game;
-mod levels;
-mod menu;
-
-mod particles;
-mod raycast;
-mod text_asset;
-
 use bevy::{
     asset::AssetMetaCheck, prelude::*, sprite_render::Material2dPlugin, window::WindowResolution,
 };
 use bevy_fix_cursor_unlock_web::FixPointerUnlockPlugin;
 use bevy_transform_interpolation::prelude::TransformInterpolationPlugin;
 
-use crate::{
+use speedwing::{
     common::{CurrentLevel, LevelData, SceneState},
+    endless::Endless,
     game::Game,
     grid::GridMaterial,
+    level_asset::{LevelAsset, LevelAssetLoader},
+    level_manifest::{LevelManifest, LevelManifestLoader},
     levels::Levels,
     menu::Menu,
     particles::ParticlesMaterial,
-    text_asset::{TextAsset, TextAssetLoader},
+    records::Records,
+    settings::Settings,
+    sim::TICK_RATE,
+    storage::SaveStorage,
 };
 
 fn main() {
+    let storage = SaveStorage::platform();
+    let records = Records::load(storage.0.as_ref());
+    let settings = Settings::load(storage.0.as_ref());
+
     App::new()
         .add_plugins(
             DefaultPlugins
@@ -55,14 +49,20 @@ fn main() {
             Material2dPlugin::<ParticlesMaterial>::default(),
         ))
         //
-        .init_asset::<TextAsset>()
-        .init_asset_loader::<TextAssetLoader>()
+        .init_asset::<LevelAsset>()
+        .init_asset_loader::<LevelAssetLoader>()
+        .init_asset::<LevelManifest>()
+        .init_asset_loader::<LevelManifestLoader>()
         .insert_resource(CurrentLevel(
             0,
             LevelData {
                 level: None,
                 start: [0., 0., 0.],
                 end: None,
+                checkpoints: Vec::new(),
+                grid: None,
+                generator: None,
+                ghost: None,
             },
         ))
         //
@@ -70,8 +70,12 @@ fn main() {
         .add_plugins(Menu)
         .add_plugins(Game)
         .add_plugins(Levels)
+        .add_plugins(Endless)
         //
+        .insert_resource(records)
+        .insert_resource(settings)
+        .insert_resource(storage)
         .insert_resource(ClearColor(Color::srgb(0., 0., 0.)))
-        .insert_resource(Time::<Fixed>::from_hz(100.))
+        .insert_resource(Time::<Fixed>::from_hz(TICK_RATE as f64))
         .run();
 }
@@ -3,7 +3,10 @@ use bevy::{
     window::{CursorGrabMode, CursorOptions},
 };
 
-use crate::common::SceneState;
+use crate::{
+    common::{ButtonQuery, CurrentLevel, SceneState},
+    endless::EndlessRun,
+};
 
 #[derive(Component)]
 struct MenuEntity;
@@ -16,7 +19,7 @@ impl Plugin for Menu {
             .add_systems(OnExit(SceneState::Menu), Menu::cleanup)
             .add_systems(
                 Update,
-                handle_play_button.run_if(in_state(SceneState::Menu)),
+                (handle_play_button, handle_endless_button).run_if(in_state(SceneState::Menu)),
             );
     }
 }
@@ -70,6 +73,30 @@ impl Menu {
                             },
                         ));
                     });
+
+                parent
+                    .spawn((
+                        Button,
+                        Node {
+                            width: Val::Px(200.),
+                            height: Val::Px(65.),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            border_radius: BorderRadius::all(Val::Px(10.)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0., 0.4, 0.8)),
+                        EndlessButton,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            Text::new("Endless"),
+                            TextFont {
+                                font_size: 32.,
+                                ..default()
+                            },
+                        ));
+                    });
             });
     }
     fn cleanup(mut commands: Commands, query: Query<Entity, With<MenuEntity>>) {
@@ -82,17 +109,17 @@ impl Menu {
 #[derive(Component)]
 struct PlayButton;
 
+#[derive(Component)]
+struct EndlessButton;
+
 fn handle_play_button(
-    mut query: Query<
-        (&Interaction, &mut BackgroundColor),
-        (Changed<Interaction>, With<PlayButton>),
-    >,
+    mut query: ButtonQuery<PlayButton>,
     mut next_state: ResMut<NextState<SceneState>>,
     mut cursor_options: Single<&mut CursorOptions>,
 ) {
     cursor_options.grab_mode = CursorGrabMode::None;
     cursor_options.visible = true;
-    for (interaction, mut bg) in &mut query {
+    for (interaction, mut bg, _) in &mut query {
         match interaction {
             Interaction::Pressed => {
                 next_state.set(SceneState::Levels);
@@ -106,3 +133,25 @@ fn handle_play_button(
         }
     }
 }
+
+fn handle_endless_button(
+    mut query: ButtonQuery<EndlessButton>,
+    mut next_state: ResMut<NextState<SceneState>>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut commands: Commands,
+) {
+    for (interaction, mut bg, _) in &mut query {
+        match interaction {
+            Interaction::Pressed => {
+                EndlessRun::start(&mut commands, &mut current_level);
+                next_state.set(SceneState::Game);
+            }
+            Interaction::Hovered => {
+                *bg = BackgroundColor(Color::srgb(0., 0.5, 0.9));
+            }
+            Interaction::None => {
+                *bg = BackgroundColor(Color::srgb(0., 0.4, 0.8));
+            }
+        }
+    }
+}
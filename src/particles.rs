@@ -9,7 +9,7 @@ use bevy::{
     sprite_render::Material2d,
 };
 
-use crate::{common::GameEntity, grid_map::GridMap};
+use crate::{common::GameEntity, density::DensityMap, grid_map::GridMap};
 
 const ATTRIBUTE_COLOUR: MeshVertexAttribute =
     MeshVertexAttribute::new("Colour", 188540917, VertexFormat::Float32x4);
@@ -119,7 +119,7 @@ impl Particles {
             time: 0.,
         })
     }
-    fn move_particles(&mut self, delta: f32, grid_map: &GridMap) {
+    fn move_particles(&mut self, delta: f32, map: &DensityMap) {
         for particle in self.particles.iter_mut() {
             particle.time += delta;
 
@@ -131,14 +131,13 @@ impl Particles {
         for particle in self.particles.iter_mut() {
             particle.position += particle.velocity * delta;
 
-            if grid_map
-                .get_world(particle.position.x, particle.position.y)
-                .is_some_and(|v| v > 0.5)
-                && let Some(normal) =
-                    grid_map.get_normal_world(particle.position.x, particle.position.y)
+            if let Some(distance) = map.get_distance_world(particle.position.x, particle.position.y)
+                && distance < 0.
+                && let Some(normal) = map.get_normal_world(particle.position.x, particle.position.y)
             {
-                particle.position -= particle.velocity * delta;
+                // push back out onto the wall surface and bounce off it
                 let normal = Vec2::new(normal.0, normal.1);
+                particle.position += normal * distance;
                 particle.velocity -= normal * 100.;
             }
         }
@@ -169,7 +168,7 @@ impl Particles {
         mut particles: ResMut<Particles>,
         mut meshes: ResMut<Assets<Mesh>>,
     ) {
-        particles.move_particles(time.delta_secs(), &grid_map);
+        particles.move_particles(time.delta_secs(), &grid_map.map);
 
         let attributes = particles.construct_attributes();
 
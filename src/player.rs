@@ -5,116 +5,139 @@ use bevy_transform_interpolation::prelude::TransformInterpolation;
 use rand::RngExt;
 
 use crate::{
-    common::{GameEntity, MainCamera, State, TimeState, Velocity, get_threshold},
+    common::{CurrentLevel, GameEntity, MainCamera, State, TimeState, Velocity},
     grid_map::GridMap,
     particles::Particles,
-    raycast::Raycaster,
+    raycast::RaycastHit,
+    sim::{self, MAX_RAYCAST, ShipState, Track},
 };
 
 #[derive(Component)]
 pub struct Player {
     pub normal: Vec2,
     pub raycast: f32,
+    pub hit: Option<RaycastHit>,
     pub particles: f32,
 }
 
-const POINTS: [(f32, f32); 6] = [
-    (0., 0.),
-    (-125., -125.),
-    (125., -125.),
-    (0., 200.),
-    (-95., 25.),
-    (95., 25.),
-];
-
 impl Player {
-    pub fn spawn(x: f32, y: f32, angle: f32, commands: &mut Commands<'_, '_>, asset_server: &Res<AssetServer>) {
-        commands.spawn(Player::bundle(x, y, angle)).with_children(|parent| {
-            parent.spawn(Sprite {
-                image: asset_server.load("orb.png"),
-                custom_size: Some(Vec2::new(100., 100.)),
-                image_mode: SpriteImageMode::Auto,
-                ..default()
+    pub fn spawn(
+        x: f32,
+        y: f32,
+        angle: f32,
+        commands: &mut Commands<'_, '_>,
+        asset_server: &Res<AssetServer>,
+    ) {
+        commands
+            .spawn(Player::bundle(x, y, angle))
+            .with_children(|parent| {
+                Player::spawn_sprites(parent, asset_server, Color::WHITE);
             });
+    }
+    // the orb and wings, tinted by color
+    pub fn spawn_sprites(
+        parent: &mut ChildSpawnerCommands,
+        asset_server: &Res<AssetServer>,
+        color: Color,
+    ) {
+        parent.spawn(Sprite {
+            image: asset_server.load("orb.png"),
+            custom_size: Some(Vec2::new(100., 100.)),
+            image_mode: SpriteImageMode::Auto,
+            color,
+            ..default()
+        });
 
-            parent.spawn((
-                Sprite {
-                    image: asset_server.load("wing.png"),
-                    custom_size: Some(Vec2::new(160., 360.)),
-                    image_mode: SpriteImageMode::Auto,
-                    ..default()
-                },
-                Transform::from_xyz(-75., 45., 0.),
-            ));
+        parent.spawn((
+            Sprite {
+                image: asset_server.load("wing.png"),
+                custom_size: Some(Vec2::new(160., 360.)),
+                image_mode: SpriteImageMode::Auto,
+                color,
+                ..default()
+            },
+            Transform::from_xyz(-75., 45., 0.),
+        ));
 
-            parent.spawn((
-                Sprite {
-                    image: asset_server.load("wing.png"),
-                    custom_size: Some(Vec2::new(160., 360.)),
-                    image_mode: SpriteImageMode::Auto,
-                    flip_x: true,
-                    ..default()
-                },
-                Transform::from_xyz(75., 45., 0.),
-            ));
-        });
+        parent.spawn((
+            Sprite {
+                image: asset_server.load("wing.png"),
+                custom_size: Some(Vec2::new(160., 360.)),
+                image_mode: SpriteImageMode::Auto,
+                flip_x: true,
+                color,
+                ..default()
+            },
+            Transform::from_xyz(75., 45., 0.),
+        ));
     }
     fn bundle(x: f32, y: f32, angle: f32) -> impl Bundle {
         (
             Self {
                 normal: Vec2::ZERO,
                 raycast: 0.,
+                hit: None,
                 particles: 0.,
             },
             Velocity(Vec3::ZERO),
             CursorMove(Vec2::ZERO),
-            Transform::from_scale(Vec3::new(0.25, 0.25, 1.)).with_translation(Vec3::new(x, y, 1.)).with_rotation(Quat::from_axis_angle(Vec3::new(0., 0., 1.), angle)),
+            sim::ship_transform(x, y, angle),
             GameEntity,
-            TransformInterpolation
+            TransformInterpolation,
         )
     }
-    pub fn movement(
-        mut query: Query<(&mut Player, &mut Velocity, &CursorMove, &Transform), With<Player>>,
-        time: Res<Time>,
+    // runs one physics step for the ship, see sim::step
+    pub fn step(
+        mut query: Query<(&mut Player, &mut Transform, &mut Velocity, &CursorMove)>,
         grid_map: Res<GridMap>,
-        mut particles: ResMut<Particles>,
-        state: Res<State>
+        current_level: Res<CurrentLevel>,
+        mut state: ResMut<State>,
     ) {
-        let Ok((mut player, mut velocity, cursor_move, transform)) = query.single_mut() else {
+        let Ok((mut player, mut transform, mut velocity, cursor_move)) = query.single_mut() else {
             return;
         };
 
-        if let Some(normal) =
-            grid_map.get_normal_world(transform.translation.x, transform.translation.y)
-        {
-            player.normal = Vec2::new(normal.0, normal.1);
-        }
-
-        let acceleration = 300.0;
-        let mut friction: f32 = 0.995;
-
-        let max_distance = 75.;
+        let ship = ShipState {
+            transform: *transform,
+            velocity: velocity.0,
+            time: state.time,
+            checkpoint: state.checkpoint,
+            normal: player.normal,
+            raycast: player.raycast,
+            hit: player.hit,
+        };
+        let ship = sim::step(
+            &Track::new(&grid_map.map, &current_level.1),
+            &ship,
+            cursor_move.0,
+        );
 
-        let angle_size = PI / 4.;
-        let dir = -cursor_move.0.normalize_or_zero();
-        let orig_angle = dir.to_angle();
-        let mut distance: f32 = max_distance;
-        for angle in -2..2 {
-            let dir = Vec2::from_angle(orig_angle + angle as f32 * angle_size);
-            distance = distance.min(Raycaster::raycast(
-                &grid_map,
-                transform.translation.xy(),
-                dir,
-                max_distance,
-                1.,
-            ));
+        *transform = ship.transform;
+        velocity.0 = ship.velocity;
+        state.time = ship.time;
+        if ship.checkpoint == 0 {
+            state.splits.clear();
+        } else if ship.checkpoint > state.checkpoint
+            && let TimeState::Timing(ticks) = ship.time
+        {
+            state.splits.push(ticks);
         }
+        state.checkpoint = ship.checkpoint;
+        player.normal = ship.normal;
+        player.raycast = ship.raycast;
+        player.hit = ship.hit;
+    }
+    pub fn emit_particles(
+        mut query: Query<(&mut Player, &Velocity, &CursorMove, &Transform)>,
+        time: Res<Time>,
+        mut particles: ResMut<Particles>,
+    ) {
+        let Ok((mut player, velocity, cursor_move, transform)) = query.single_mut() else {
+            return;
+        };
 
-        player.raycast = distance;
-
-        let distance = (distance / max_distance).powi(4);
-
-        let speed_mul = 1. + (1. - distance) * 5.;
+        let orig_angle = (-cursor_move.0.normalize_or_zero()).to_angle();
+        let distance = (player.raycast / MAX_RAYCAST).powi(4);
 
         player.particles += velocity.0.length() / 10. * time.delta_secs();
         while player.particles >= 2. {
@@ -126,126 +149,44 @@ impl Player {
 
             let off = Vec2::new(50., -70.);
 
-            let dir = Vec2::from_angle(orig_angle + PI/8.);
+            let dir = Vec2::from_angle(orig_angle + PI / 8.);
             particles.spawn(
                 transform.transform_point(Vec3::new(off.x, off.y, 0.)).xy(),
                 (dir + Vec2::new(
                     rng.random_range(-spread..spread),
                     rng.random_range(-spread..spread),
-                )) * velocity.0.length().powf(0.5) * 10.,
+                )) * velocity.0.length().powf(0.5)
+                    * 10.,
                 Color::linear_rgb(
                     0.05 + rng.random_range(-sc..sc) + (1. - distance) * 0.1,
                     0.35 + rng.random_range(-sc..sc) + (1. - distance) * 0.1,
                     0.7 + rng.random_range(-sc..sc) + (1. - distance) * 0.1,
                 ),
-                (1. + rng.random_range(-spread_size..spread_size)) * (velocity.0.length() / 50.).powf(0.5),
+                (1. + rng.random_range(-spread_size..spread_size))
+                    * (velocity.0.length() / 50.).powf(0.5),
                 (2. + rng.random_range(-sl..sl)) * (1. - distance + 0.2),
             );
 
-            let dir = Vec2::from_angle(orig_angle - PI/8.);
-           particles.spawn(
+            let dir = Vec2::from_angle(orig_angle - PI / 8.);
+            particles.spawn(
                 transform.transform_point(Vec3::new(-off.x, off.y, 0.)).xy(),
                 (dir + Vec2::new(
                     rng.random_range(-spread..spread),
                     rng.random_range(-spread..spread),
-                )) * velocity.0.length().powf(0.5) * 10.,
+                )) * velocity.0.length().powf(0.5)
+                    * 10.,
                 Color::linear_rgb(
                     0.05 + rng.random_range(-sc..sc) + (1. - distance) * 0.1,
                     0.35 + rng.random_range(-sc..sc) + (1. - distance) * 0.1,
                     0.7 + rng.random_range(-sc..sc) + (1. - distance) * 0.1,
                 ),
-                (1. + rng.random_range(-spread_size..spread_size)) * (velocity.0.length() / 50.).powf(0.5),
+                (1. + rng.random_range(-spread_size..spread_size))
+                    * (velocity.0.length() / 50.).powf(0.5),
                 (2. + rng.random_range(-sl..sl)) * (1. - distance + 0.2),
             );
 
             player.particles -= 2.;
         }
-
-        let mut direction = Vec3::ZERO;
-        if cursor_move.0.length() >= 0.1 {
-            direction += cursor_move.0.extend(0.);
-        } else if !matches!(state.time, TimeState::Finished(_)) {
-            friction = match state.time {
-                TimeState::Finished(_) => 0.97,
-                _ => 0.95
-            };
-        }
-
-        if direction.length() > 0.0 {
-            velocity.0 += direction * acceleration * speed_mul * time.delta_secs();
-        }
-
-        velocity.0 *= friction.powf(time.delta_secs() * 100.);
-    }
-    fn is_colliding(
-        &self,
-        grid_map: &Res<GridMap>,
-        threshold: f32,
-        transform: &Transform,
-    ) -> Option<Vec3> {
-        let mut normal = Vec3::ZERO;
-        for offset in POINTS {
-            let offset = transform.transform_point(Vec3::new(offset.0, offset.1, 0.));
-            if grid_map
-                .get_world(offset.x, offset.y)
-                .is_some_and(|v| v > threshold + 0.02)
-                && let Some(pnormal) = grid_map.get_normal_world(offset.x, offset.y)
-            {
-                normal += Vec3::new(pnormal.0, pnormal.1, 0.);
-            }
-        }
-        match normal.length() > 0. {
-            true => Some(normal.normalize_or_zero()),
-            false => None,
-        }
-    }
-    pub fn get_points(transform: &Transform) -> Vec<Vec2> {
-        let mut points = Vec::new();
-        for offset in POINTS {
-            points.push(transform.transform_point(Vec3::new(offset.0, offset.1, 0.)).xy());
-        }
-        points
-    }
-    pub fn apply_velocity(
-        mut query: Query<(&Player, &mut Transform, &mut Velocity, &CursorMove)>,
-        time: Res<Time>,
-        grid_map: Res<GridMap>,
-    ) {
-        let threshold = get_threshold();
-        let Ok((player, mut transform, mut velocity, cursor_move)) = query.single_mut() else {
-            return;
-        };
-
-        transform.translation += velocity.0 * time.delta_secs();
-
-        if let Some(normal) = player.is_colliding(&grid_map, threshold, &transform) {
-            transform.translation -= velocity.0 * time.delta_secs();
-
-            let mut dir = 0.05;
-
-            while let Some(normal) = player.is_colliding(&grid_map, threshold, &transform) {
-                transform.translation -= normal * dir;
-                dir = -dir - 0.05 * dir.signum();
-                transform.translation += normal * dir;
-            }
-
-            let change = -velocity.0.dot(normal) * normal;
-            velocity.0 = velocity.0 + change;
-
-            transform.translation += velocity.0 * time.delta_secs();
-        }
-
-        if cursor_move.0.length() < 0.1 {
-            return;
-        }
-
-        let target_angle = cursor_move.0.y.atan2(cursor_move.0.x) - PI / 2.;
-        let target_rotation = Quat::from_rotation_z(target_angle);
-
-        transform.rotation = transform.rotation.slerp(
-            target_rotation,
-            (1. - 0.002_f32.powf(time.delta_secs())) * (0.2 + cursor_move.0.length() * 1.3),
-        );
     }
     pub fn camera_follow(
         player_query: Query<(&Transform, &Velocity), (With<Player>, Without<MainCamera>)>,
@@ -264,10 +205,13 @@ impl Player {
             return;
         };
 
-        state.follow = state.follow.lerp(match state.time {
-            TimeState::Finished(_) => 0.,
-            _ => 1.
-        }, 1. - 0.01_f32.powf(time.delta_secs()));
+        state.follow = state.follow.lerp(
+            match state.time {
+                TimeState::Finished(_) => 0.,
+                _ => 1.,
+            },
+            1. - 0.01_f32.powf(time.delta_secs()),
+        );
 
         camera_transform.translation = camera_transform.translation.lerp(
             player_transform.translation,
@@ -280,13 +224,8 @@ impl Player {
         );
     }
     pub fn draw_points(&self, gizmos: &mut Gizmos, transform: &Transform) {
-        for offset in POINTS {
-            let offset = transform.transform_point(Vec3::new(offset.0, offset.1, 0.));
-            gizmos.circle_2d(
-                Vec2::new(offset.x, offset.y),
-                2.5,
-                Color::linear_rgba(1., 0., 0., 0.8),
-            );
+        for point in sim::hull_points(transform) {
+            gizmos.circle_2d(point, 2.5, Color::linear_rgba(1., 0., 0., 0.8));
         }
     }
 }
@@ -1,23 +1,191 @@
 use bevy::prelude::*;
 
-use crate::grid_map::GridMap;
+use crate::density::DensityMap;
 
 pub struct Raycaster;
 
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+    pub point: Vec2,
+    // points out of the terrain, back towards open space
+    pub normal: Vec2,
+    pub distance: f32,
+    pub cell: IVec2,
+}
+
 impl Raycaster {
-    pub fn raycast(grid_map: &GridMap, start: Vec2, dir: Vec2, distance: f32, step: f32) -> f32 {
+    // walks the cells along the ray in grid space and solves the bilinear
+    // density for the threshold crossing inside each one
+    pub fn raycast(
+        map: &DensityMap,
+        start: Vec2,
+        dir: Vec2,
+        max_distance: f32,
+    ) -> Option<RaycastHit> {
         let dir = dir.normalize_or_zero();
-        let mut current = step;
-        while current <= distance {
-            if grid_map
-                .get_world(start.x + current * dir.x, start.y + current * dir.y)
-                .is_some_and(|v| v >= 0.5)
+        if dir == Vec2::ZERO {
+            return None;
+        }
+
+        let scale = map.scale();
+        let max_t = max_distance / scale;
+
+        // sphere trace through open space using the distance field, then walk
+        // cells from there once a wall is close
+        let mut origin = start / scale;
+        let mut skipped = 0.;
+        while let Some(distance) = map.get_distance(origin.x, origin.y)
+            && distance > 2.
+        {
+            let advance = distance - 1.;
+            if skipped + advance >= max_t {
+                return None;
+            }
+            origin += dir * advance;
+            skipped += advance;
+        }
+        let max_t = max_t - skipped;
+
+        let mut cell = origin.floor().as_ivec2();
+        let step = IVec2::new(dir.x.signum() as i32, dir.y.signum() as i32);
+        let t_delta = Vec2::new(
+            if dir.x != 0. {
+                1. / dir.x.abs()
+            } else {
+                f32::INFINITY
+            },
+            if dir.y != 0. {
+                1. / dir.y.abs()
+            } else {
+                f32::INFINITY
+            },
+        );
+        let mut t_max = Vec2::new(
+            if dir.x > 0. {
+                (cell.x as f32 + 1. - origin.x) / dir.x
+            } else if dir.x < 0. {
+                (origin.x - cell.x as f32) / -dir.x
+            } else {
+                f32::INFINITY
+            },
+            if dir.y > 0. {
+                (cell.y as f32 + 1. - origin.y) / dir.y
+            } else if dir.y < 0. {
+                (origin.y - cell.y as f32) / -dir.y
+            } else {
+                f32::INFINITY
+            },
+        );
+
+        let mut t = 0.;
+        loop {
+            let t_exit = t_max.x.min(t_max.y).min(max_t);
+
+            if let Some((hit_t, gradient)) =
+                Raycaster::cell_crossing(map, cell, origin, dir, t, t_exit)
             {
-                return current;
+                let normal = (-gradient).normalize_or(-dir);
+                return Some(RaycastHit {
+                    point: (origin + dir * hit_t) * scale,
+                    normal,
+                    distance: (skipped + hit_t) * scale,
+                    cell,
+                });
+            }
+
+            if t_exit >= max_t {
+                return None;
+            }
+
+            if t_max.x < t_max.y {
+                cell.x += step.x;
+                t = t_max.x;
+                t_max.x += t_delta.x;
+            } else {
+                cell.y += step.y;
+                t = t_max.y;
+                t_max.y += t_delta.y;
+            }
+        }
+    }
+
+    pub fn raycast_fan(
+        map: &DensityMap,
+        start: Vec2,
+        angles: impl IntoIterator<Item = f32>,
+        max_distance: f32,
+    ) -> Vec<Option<RaycastHit>> {
+        angles
+            .into_iter()
+            .map(|angle| Raycaster::raycast(map, start, Vec2::from_angle(angle), max_distance))
+            .collect()
+    }
+
+    // first t in [t0, t1] where the ray reaches the threshold inside the cell,
+    // along with the density gradient there
+    fn cell_crossing(
+        map: &DensityMap,
+        cell: IVec2,
+        origin: Vec2,
+        dir: Vec2,
+        t0: f32,
+        t1: f32,
+    ) -> Option<(f32, Vec2)> {
+        let v00 = map.get(cell.x, cell.y)?;
+        let v10 = map.get(cell.x + 1, cell.y)?;
+        let v01 = map.get(cell.x, cell.y + 1)?;
+        let v11 = map.get(cell.x + 1, cell.y + 1)?;
+
+        let threshold = map.threshold();
+        if v00.max(v10).max(v01).max(v11) < threshold {
+            return None;
+        }
+
+        // f(x, y) = a + b x + c y + d x y over the cell, whose saddle value is
+        // what density::saddle_solid uses to join diagonal cells in the mesh
+        let a = v00;
+        let b = v10 - v00;
+        let c = v01 - v00;
+        let d = v00 - v10 - v01 + v11;
+
+        let p = origin - cell.as_vec2();
+
+        // f along the ray as qa t^2 + qb t + qc
+        let qa = d * dir.x * dir.y;
+        let qb = b * dir.x + c * dir.y + d * (p.x * dir.y + p.y * dir.x);
+        let qc = a + b * p.x + c * p.y + d * p.x * p.y - threshold;
+
+        let gradient = |t: f32| {
+            let local = p + dir * t;
+            Vec2::new(b + d * local.y, c + d * local.x)
+        };
+
+        let f0 = qa * t0 * t0 + qb * t0 + qc;
+        if f0 >= 0. {
+            return Some((t0, gradient(t0)));
+        }
+
+        let root = if qa.abs() < 1e-6 {
+            if qb.abs() < 1e-9 {
+                return None;
+            }
+            -qc / qb
+        } else {
+            let discriminant = qb * qb - 4. * qa * qc;
+            if discriminant < 0. {
+                return None;
             }
+            let sqrt = discriminant.sqrt();
+            let r1 = (-qb - sqrt) / (2. * qa);
+            let r2 = (-qb + sqrt) / (2. * qa);
+            let (r1, r2) = (r1.min(r2), r1.max(r2));
+            if r1 >= t0 { r1 } else { r2 }
+        };
 
-            current += step
+        if root >= t0 && root <= t1 {
+            Some((root, gradient(root)))
+        } else {
+            None
         }
-        distance
     }
 }
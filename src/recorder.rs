@@ -0,0 +1,175 @@
+#[cfg(not(target_arch = "wasm32"))]
+use arboard::Clipboard;
+
+use bevy::prelude::*;
+
+use crate::{
+    common::{CurrentLevel, State, TimeState, Velocity},
+    player::{CursorMove, Player},
+    replay::Replay,
+    sim,
+    storage::{SaveStorage, Storage},
+};
+
+// where C keeps the last copied replay
+const REPLAY_KEY: &str = "replays/last";
+
+// records the session being played, paused while watching a replay until the next restart
+#[derive(Resource)]
+pub struct Recorder {
+    pub replay: Replay,
+    active: bool,
+    // the level has been changed in the editor since it was loaded, so the session
+    // no longer matches the level hash and its runs and replays are kept out of records
+    edited: bool,
+}
+
+impl Recorder {
+    pub fn new(level_hash: u64) -> Self {
+        Self {
+            replay: Replay::new(level_hash),
+            active: true,
+            edited: false,
+        }
+    }
+    pub fn active(&self) -> bool {
+        self.active
+    }
+    pub fn edited(&self) -> bool {
+        self.edited
+    }
+    pub fn mark_edited(&mut self) {
+        if !self.edited {
+            info!("level edited, runs won't count until it is saved and loaded again");
+            self.edited = true;
+        }
+    }
+}
+
+// a replay being fed to the ship in place of the mouse
+#[derive(Resource)]
+pub struct Playback {
+    replay: Replay,
+    tick: usize,
+}
+
+pub fn record_input(mut recorder: ResMut<Recorder>, query: Query<&CursorMove, With<Player>>) {
+    if !recorder.active {
+        return;
+    }
+    let Ok(cursor_move) = query.single() else {
+        return;
+    };
+    recorder.replay.inputs.push(cursor_move.0);
+}
+
+pub fn play_input(
+    mut playback: ResMut<Playback>,
+    mut query: Query<(&mut CursorMove, &mut Transform, &mut Velocity), With<Player>>,
+    mut state: ResMut<State>,
+    current_level: Res<CurrentLevel>,
+    mut commands: Commands,
+) {
+    let Ok((mut cursor_move, mut transform, mut velocity)) = query.single_mut() else {
+        return;
+    };
+
+    let tick = playback.tick;
+    let Some(input) = playback.replay.inputs.get(tick).copied() else {
+        info!("replay finished");
+        cursor_move.0 = Vec2::ZERO;
+        commands.remove_resource::<Playback>();
+        return;
+    };
+
+    // the same reset as respawning the ship, done in place so this tick already uses it
+    if tick == 0 || playback.replay.restarts.contains(&(tick as u32)) {
+        let start = current_level.1.start;
+        *transform = sim::ship_transform(start[0], start[1], start[2]);
+        velocity.0 = Vec3::ZERO;
+        state.time = TimeState::None;
+    }
+
+    cursor_move.0 = input;
+    playback.tick += 1;
+}
+
+// a replay from the clipboard, or the one last copied if the clipboard doesn't hold one
+fn pasted_replay(storage: &dyn Storage) -> Option<Replay> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(clipboard) = &mut Clipboard::new()
+        && let Ok(text) = clipboard.get_text()
+        && let Ok(replay) = serde_json::from_str(&text)
+    {
+        return Some(replay);
+    }
+
+    serde_json::from_str(&storage.read(REPLAY_KEY)?).ok()
+}
+
+pub fn replay_keys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut recorder: ResMut<Recorder>,
+    playback: Option<Res<Playback>>,
+    current_level: Res<CurrentLevel>,
+    storage: Res<SaveStorage>,
+    mut commands: Commands,
+) {
+    let level_hash = || current_level.1.content_hash();
+
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        if playback.is_some() || !recorder.active {
+            commands.remove_resource::<Playback>();
+            *recorder = Recorder {
+                edited: recorder.edited,
+                ..Recorder::new(level_hash())
+            };
+        } else {
+            let tick = recorder.replay.inputs.len() as u32;
+            recorder.replay.restarts.push(tick);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyC) && recorder.edited {
+        warn!("can't copy a replay of an edited level, save and load it first");
+    } else if keyboard_input.just_pressed(KeyCode::KeyC)
+        && let Ok(save) = serde_json::to_string(&recorder.replay)
+    {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Ok(clipboard) = &mut Clipboard::new() {
+            clipboard.set_text(&save).ok();
+        }
+        if let Err(error) = storage.0.write(REPLAY_KEY, &save) {
+            warn!("couldn't keep the replay: {}", error);
+        }
+
+        info!(save);
+    }
+
+    let mut replay = None;
+    if keyboard_input.just_pressed(KeyCode::KeyB) {
+        replay = Some(recorder.replay.clone());
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyV) {
+        replay = pasted_replay(storage.0.as_ref());
+        if replay.is_none() {
+            warn!("no replay on the clipboard or kept from before");
+        }
+    }
+
+    let Some(replay) = replay else {
+        return;
+    };
+    if recorder.edited {
+        warn!("can't play replays on an edited level, save and load it first");
+        return;
+    }
+    match replay.check(level_hash()) {
+        Ok(()) => {
+            recorder.active = false;
+            commands.insert_resource(Playback { replay, tick: 0 });
+        }
+        Err(error) => warn!("can't play replay: {}", error),
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::{State, TimeState},
+    ghost::Ghosts,
+    recorder::Recorder,
+    replay::Replay,
+    storage::{SaveStorage, Storage},
+};
+
+// finished runs kept per level
+const HISTORY: usize = 20;
+
+const RECORDS_KEY: &str = "records";
+
+// best runs' replays are kept apart from the records, so saving a time doesn't
+// rewrite the replay of every level played
+fn replay_key(level_hash: u64) -> String {
+    format!("replays/best/{:016x}", level_hash)
+}
+
+// best times and recent runs on every level played, by level hash
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct Records {
+    levels: HashMap<u64, LevelRecord>,
+    // changed since the last save, and the levels whose best replay changed with it
+    #[serde(skip)]
+    dirty: bool,
+    #[serde(skip)]
+    new_replays: HashSet<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct LevelRecord {
+    // runs started, finished or not
+    pub attempts: u32,
+    pub best: Option<BestRun>,
+    // the last HISTORY finished runs, oldest first
+    pub runs: Vec<Run>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BestRun {
+    pub ticks: u32,
+    // saved under replay_key
+    #[serde(skip)]
+    pub replay: Option<Replay>,
+    // the tick each checkpoint was passed on
+    #[serde(default)]
+    pub splits: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Run {
+    pub attempt: u32,
+    pub ticks: u32,
+}
+
+impl Records {
+    pub fn get(&self, level_hash: u64) -> Option<&LevelRecord> {
+        self.levels.get(&level_hash)
+    }
+    pub fn best(&self, level_hash: u64) -> Option<&BestRun> {
+        self.get(level_hash)?.best.as_ref()
+    }
+    // starts empty if there's nothing saved or it can't be read
+    pub fn load(storage: &dyn Storage) -> Self {
+        let mut records = match storage.read(RECORDS_KEY) {
+            Some(save) => serde_json::from_str(&save).unwrap_or_else(|error| {
+                warn!("couldn't read saved records: {}", error);
+                Records::default()
+            }),
+            None => Records::default(),
+        };
+
+        for (level_hash, record) in records.levels.iter_mut() {
+            if let Some(best) = &mut record.best {
+                best.replay = storage
+                    .read(&replay_key(*level_hash))
+                    .and_then(|save| serde_json::from_str(&save).ok());
+            }
+        }
+        records
+    }
+    // writes whatever changed since the last save
+    pub fn save(&mut self, storage: &dyn Storage) {
+        if !self.dirty {
+            return;
+        }
+
+        let write = |key: &str, save: serde_json::Result<String>| {
+            save.map_err(|error| error.to_string())
+                .and_then(|save| storage.write(key, &save).map_err(|error| error.to_string()))
+        };
+        let mut result = Ok(());
+        for level_hash in self.new_replays.iter() {
+            if let Some(replay) = self.best(*level_hash).and_then(|best| best.replay.as_ref()) {
+                result = result.and(write(
+                    &replay_key(*level_hash),
+                    serde_json::to_string(replay),
+                ));
+            }
+        }
+        let result = result.and(write(RECORDS_KEY, serde_json::to_string(self)));
+
+        // replays that didn't make it are tried again with the next change
+        self.dirty = false;
+        match result {
+            Ok(()) => self.new_replays.clear(),
+            Err(error) => warn!("couldn't save records: {}", error),
+        }
+    }
+}
+
+// counts attempts as they start and keeps finished runs, see save_records for when they're saved
+pub fn track_runs(
+    mut state: ResMut<State>,
+    recorder: Res<Recorder>,
+    mut records: ResMut<Records>,
+    mut ghosts: ResMut<Ghosts>,
+    mut last: Local<Option<TimeState>>,
+) {
+    let previous = last.replace(state.time).unwrap_or(TimeState::None);
+    let level_hash = recorder.replay.level_hash;
+
+    // splits are compared against the best as it was when the run started
+    if let (TimeState::None, TimeState::Timing(_)) = (previous, state.time) {
+        state.best_splits = records
+            .best(level_hash)
+            .map(|best| best.splits.clone())
+            .unwrap_or_default();
+    }
+
+    // replays being watched and runs on a level edited since it was loaded don't count
+    if !recorder.active() || recorder.edited() {
+        return;
+    }
+
+    match (previous, state.time) {
+        (TimeState::None, TimeState::Timing(_)) => {
+            records.levels.entry(level_hash).or_default().attempts += 1;
+        }
+        (TimeState::Timing(_), TimeState::Finished(ticks)) => {
+            let record = records.levels.entry(level_hash).or_default();
+            record.runs.push(Run {
+                attempt: record.attempts,
+                ticks,
+            });
+            if record.runs.len() > HISTORY {
+                record.runs.remove(0);
+            }
+
+            if record.best.as_ref().is_none_or(|best| ticks < best.ticks) {
+                info!("new best time of {} ticks", ticks);
+                record.best = Some(BestRun {
+                    ticks,
+                    replay: Some(recorder.replay.last_attempt()),
+                    splits: state.splits.clone(),
+                });
+                records.new_replays.insert(level_hash);
+                ghosts.best_changed();
+            }
+        }
+        _ => return,
+    }
+
+    records.dirty = true;
+}
+
+// saves between runs rather than as they happen, so the physics never waits on storage
+pub fn save_records(mut records: ResMut<Records>, state: Res<State>, storage: Res<SaveStorage>) {
+    if !matches!(state.time, TimeState::Timing(_)) {
+        records.save(storage.0.as_ref());
+    }
+}
+
+// leaving a level part way through a run still keeps the attempt
+pub fn flush_records(mut records: ResMut<Records>, storage: Res<SaveStorage>) {
+    records.save(storage.0.as_ref());
+}
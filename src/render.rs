@@ -1,7 +1,12 @@
+use std::cmp::Ordering;
+
 use bevy::prelude::*;
 
 use crate::{
-    common::{CurrentLevel, FinishGizmoGroup, MainCamera, MovementGizmoGroup, State, Velocity},
+    common::{
+        CurrentLevel, FinishGizmoGroup, MainCamera, MovementGizmoGroup, State, Velocity,
+        viewport_rect,
+    },
     grid_map::GridMap,
     player::{CursorMove, Player},
 };
@@ -34,6 +39,7 @@ pub fn render_movement(
     mut movement_gizmos: Gizmos<MovementGizmoGroup>,
     mut gizmos: Gizmos,
     state: Res<State>,
+    grid_map: Res<GridMap>,
 ) {
     let Ok((player, pos, cursor_move, velocity)) = query.single_mut() else {
         return;
@@ -76,6 +82,21 @@ pub fn render_movement(
             Color::linear_rgba(0.2, 0.25, 0.8, 0.8),
         );
 
+        if let Some(hit) = player.hit {
+            let scale = grid_map.map.scale();
+            gizmos.circle_2d(hit.point, 5., Color::linear_rgba(1., 1., 0., 0.8));
+            gizmos.line_2d(
+                hit.point,
+                hit.point + hit.normal * 30.,
+                Color::linear_rgba(1., 1., 0., 0.8),
+            );
+            gizmos.rect_2d(
+                Isometry2d::from_translation((hit.cell.as_vec2() + 0.5) * scale),
+                Vec2::splat(scale),
+                Color::linear_rgba(1., 1., 0., 0.4),
+            );
+        }
+
         player.draw_points(&mut gizmos, &pos);
     }
 }
@@ -114,16 +135,32 @@ pub fn draw_dots(
     if state.debug
         && let Ok((camera, camera_transform)) = camera_query.single()
     {
-        grid_map.draw_dots(&mut gizmos, camera, camera_transform);
-        grid_map.draw_segments(&mut gizmos, camera, camera_transform);
-        grid_map.draw_borders(&mut gizmos, camera, camera_transform);
+        let view = viewport_rect(camera, camera_transform);
+        grid_map.draw_dots(&mut gizmos, view);
+        grid_map.draw_segments(&mut gizmos, view);
+        grid_map.draw_borders(&mut gizmos, view);
     }
 }
 
 pub fn render_finish(
     mut gizmos: Gizmos<FinishGizmoGroup>,
     current_level: Res<CurrentLevel>,
+    state: Res<State>,
 ) {
+    // the next checkpoint stands out, passed ones fade
+    for (i, checkpoint) in current_level.1.checkpoints.iter().enumerate() {
+        let color = match i.cmp(&state.checkpoint) {
+            Ordering::Less => Color::linear_rgba(1., 1., 0., 0.2),
+            Ordering::Equal => Color::linear_rgba(1., 1., 0., 0.9),
+            Ordering::Greater => Color::linear_rgba(1., 1., 0., 0.5),
+        };
+        gizmos.line_2d(
+            Vec2::from_array(checkpoint[0]),
+            Vec2::from_array(checkpoint[1]),
+            color,
+        );
+    }
+
     let Some(end) = current_level.1.end else {
         return;
     };
@@ -0,0 +1,83 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bevy::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+use crate::sim::{PHYSICS_VERSION, SHIP};
+
+// every tick's input from entering a level, enough to play the session back through sim::step
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replay {
+    pub physics_version: u32,
+    pub ship: String,
+    pub level_hash: u64,
+    #[serde(with = "inputs")]
+    pub inputs: Vec<Vec2>,
+    // ticks the ship was put back at the start before
+    pub restarts: Vec<u32>,
+}
+
+impl Replay {
+    pub fn new(level_hash: u64) -> Self {
+        Self {
+            physics_version: PHYSICS_VERSION,
+            ship: SHIP.to_string(),
+            level_hash,
+            inputs: Vec::new(),
+            restarts: Vec::new(),
+        }
+    }
+    // just the inputs since the last restart
+    pub fn last_attempt(&self) -> Replay {
+        let first = self.restarts.last().map_or(0, |tick| *tick as usize);
+        Replay {
+            inputs: self.inputs[first.min(self.inputs.len())..].to_vec(),
+            restarts: Vec::new(),
+            ..self.clone()
+        }
+    }
+    // why the replay can't be played on this level, if it can't
+    pub fn check(&self, level_hash: u64) -> Result<(), String> {
+        if self.physics_version != PHYSICS_VERSION {
+            return Err(format!(
+                "replay was recorded with physics version {}, this is version {}",
+                self.physics_version, PHYSICS_VERSION
+            ));
+        }
+        if self.ship != SHIP {
+            return Err(format!("replay was recorded with the {} ship", self.ship));
+        }
+        if self.level_hash != level_hash {
+            return Err("replay was recorded on a different level".to_string());
+        }
+        Ok(())
+    }
+}
+
+// inputs are stored as little endian f32 pairs so they come back bit for bit
+mod inputs {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(inputs: &[Vec2], serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes: Vec<u8> = inputs
+            .iter()
+            .flat_map(|input| input.to_array())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec2>, D::Error> {
+        let save = String::deserialize(deserializer)?;
+        let bytes = STANDARD.decode(save).map_err(de::Error::custom)?;
+        if bytes.len() % 8 != 0 {
+            return Err(de::Error::custom(
+                "input data is not a whole number of ticks",
+            ));
+        }
+        let float = |bytes: &[u8]| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|tick| Vec2::new(float(&tick[0..4]), float(&tick[4..8])))
+            .collect())
+    }
+}
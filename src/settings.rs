@@ -0,0 +1,55 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ghost::GhostSource,
+    storage::{SaveStorage, Storage},
+};
+
+const SETTINGS_KEY: &str = "settings";
+
+// choices kept between sessions, missing ones take their defaults
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Settings {
+    pub debug: bool,
+    pub ghost: GhostSource,
+}
+
+impl Settings {
+    pub fn load(storage: &dyn Storage) -> Self {
+        match storage.read(SETTINGS_KEY) {
+            Some(save) => serde_json::from_str(&save).unwrap_or_else(|error| {
+                warn!("couldn't read saved settings: {}", error);
+                Settings::default()
+            }),
+            None => Settings::default(),
+        }
+    }
+    pub fn save(&self, storage: &dyn Storage) {
+        let result = serde_json::to_string(self)
+            .map_err(|error| error.to_string())
+            .and_then(|save| {
+                storage
+                    .write(SETTINGS_KEY, &save)
+                    .map_err(|error| error.to_string())
+            });
+        if let Err(error) = result {
+            warn!("couldn't save settings: {}", error);
+        }
+    }
+}
+
+// the settings along with where they are saved, for systems that change them
+#[derive(SystemParam)]
+pub struct SettingsStore<'w> {
+    pub settings: ResMut<'w, Settings>,
+    storage: Res<'w, SaveStorage>,
+}
+
+impl SettingsStore<'_> {
+    pub fn update(&mut self, change: impl FnOnce(&mut Settings)) {
+        change(&mut self.settings);
+        self.settings.save(self.storage.0.as_ref());
+    }
+}
@@ -0,0 +1,422 @@
+use std::f32::consts::PI;
+
+use bevy::{math::ops, prelude::*};
+
+use crate::{
+    common::{FINISH_DISTANCE, LevelData, TimeState, dist_to_segment},
+    density::DensityMap,
+    raycast::{RaycastHit, Raycaster},
+};
+
+// the simulation always advances in steps of this many per second
+pub const TICK_RATE: u32 = 100;
+pub const TICK: f32 = 1. / TICK_RATE as f32;
+
+// bump whenever a change here would make recorded inputs play out differently
+pub const PHYSICS_VERSION: u32 = 2;
+
+const ACCELERATION: f32 = 300.;
+// how far the fan of rays looks for walls, being close to one speeds the ship up
+pub const MAX_RAYCAST: f32 = 75.;
+
+const MAX_SUBSTEPS: usize = 4;
+const MAX_DEPENETRATION_STEPS: usize = 4;
+// gap left between the hull and the wall after a sweep hit
+const SKIN: f32 = 0.5;
+// how far into the terrain a hull point may sit before being pushed out
+const PENETRATION_TOLERANCE: f32 = 0.25;
+
+const POINTS: [(f32, f32); 6] = [
+    (0., 0.),
+    (-125., -125.),
+    (125., -125.),
+    (0., 200.),
+    (-95., 25.),
+    (95., 25.),
+];
+
+// POINTS are in sprite space, the ship is drawn at this scale
+const SCALE: f32 = 0.25;
+
+// replays only play back on the ship they were recorded with
+pub const SHIP: &str = "speedwing";
+
+// POINTS in order around the outline of the ship
+const HULL: [usize; 6] = [3, 5, 2, 0, 1, 4];
+
+pub fn ship_transform(x: f32, y: f32, angle: f32) -> Transform {
+    Transform::from_scale(Vec3::new(SCALE, SCALE, 1.))
+        .with_translation(Vec3::new(x, y, 1.))
+        .with_rotation(Quat::from_axis_angle(Vec3::new(0., 0., 1.), angle))
+}
+
+// furthest the hull reaches from the ship's centre in world units, the room it
+// needs to turn in place as it steers
+pub fn hull_radius() -> f32 {
+    POINTS
+        .iter()
+        .map(|point| Vec2::new(point.0, point.1).length())
+        .fold(0., f32::max)
+        * SCALE
+}
+
+pub fn hull_points(transform: &Transform) -> Vec<Vec2> {
+    let mut points = Vec::new();
+    for offset in POINTS {
+        points.push(
+            transform
+                .transform_point(Vec3::new(offset.0, offset.1, 0.))
+                .xy(),
+        );
+    }
+    points
+}
+
+// points along the hull outline, no further apart than spacing
+pub fn hull_samples(transform: &Transform, spacing: f32) -> Vec<Vec2> {
+    let points = hull_points(transform);
+    let mut samples = Vec::new();
+    for i in 0..HULL.len() {
+        let a = points[HULL[i]];
+        let b = points[HULL[(i + 1) % HULL.len()]];
+        let steps = (a.distance(b) / spacing).ceil().max(1.) as usize;
+        for step in 0..steps {
+            samples.push(a.lerp(b, step as f32 / steps as f32));
+        }
+    }
+    samples
+}
+
+// the parts of a level the ship interacts with
+#[derive(Clone, Copy)]
+pub struct Track<'a> {
+    pub map: &'a DensityMap,
+    pub end: Option<[[f32; 2]; 2]>,
+    pub checkpoints: &'a [[[f32; 2]; 2]],
+}
+
+impl<'a> Track<'a> {
+    pub fn new(map: &'a DensityMap, level: &'a LevelData) -> Self {
+        Self {
+            map,
+            end: level.end,
+            checkpoints: &level.checkpoints,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShipState {
+    pub transform: Transform,
+    pub velocity: Vec3,
+    pub time: TimeState,
+    // checkpoints passed so far this run
+    pub checkpoint: usize,
+    // what the ship sensed during the last step, used for drawing
+    pub normal: Vec2,
+    pub raycast: f32,
+    pub hit: Option<RaycastHit>,
+}
+
+impl ShipState {
+    pub fn new(start: [f32; 3]) -> Self {
+        Self {
+            transform: ship_transform(start[0], start[1], start[2]),
+            velocity: Vec3::ZERO,
+            time: TimeState::None,
+            checkpoint: 0,
+            normal: Vec2::ZERO,
+            raycast: MAX_RAYCAST,
+            hit: None,
+        }
+    }
+}
+
+// advances the ship by one tick, input is the cursor offset the ship steers towards.
+// a tick always runs in this order:
+//   1. the timer starts on the first non-zero input, or counts the tick if already running
+//   2. the velocity gets input, wall proximity and friction applied
+//   3. the ship moves along the velocity, sliding along walls, turns towards input
+//      and is pushed back out of anything it ended up in
+//   4. the new position is checked against the next checkpoint, or the finish line once
+//      every checkpoint has been passed
+// nothing here reads the clock or an rng, and the math goes through bevy's libm backed ops,
+// so the same state and input give the same result down to the bit on every platform
+pub fn step(track: &Track, state: &ShipState, input: Vec2) -> ShipState {
+    let mut next = *state;
+    advance_time(&mut next, input);
+    accelerate(track.map, &mut next, input, TICK);
+    apply_velocity(track.map, &mut next, input, TICK);
+    check_gates(track, &mut next);
+    next
+}
+
+// plays inputs from the start, returning the state after every tick
+pub fn simulate(track: &Track, start: [f32; 3], inputs: &[Vec2]) -> Vec<ShipState> {
+    let mut state = ShipState::new(start);
+    inputs
+        .iter()
+        .map(|input| {
+            state = step(track, &state, *input);
+            state
+        })
+        .collect()
+}
+
+fn advance_time(state: &mut ShipState, input: Vec2) {
+    match state.time {
+        TimeState::None => {
+            state.checkpoint = 0;
+            if input != Vec2::ZERO {
+                state.time = TimeState::Timing(0);
+            }
+        }
+        TimeState::Timing(ticks) => state.time = TimeState::Timing(ticks + 1),
+        TimeState::Finished(_) => (),
+    };
+}
+
+fn accelerate(map: &DensityMap, state: &mut ShipState, input: Vec2, dt: f32) {
+    let position = state.transform.translation;
+    if let Some(normal) = map.get_normal_world(position.x, position.y) {
+        state.normal = Vec2::new(normal.0, normal.1);
+    }
+
+    let mut friction: f32 = 0.995;
+
+    let angle_size = PI / 4.;
+    let orig_angle = (-input.normalize_or_zero()).to_angle();
+    state.hit = Raycaster::raycast_fan(
+        map,
+        position.xy(),
+        (-2..2).map(|angle| orig_angle + angle as f32 * angle_size),
+        MAX_RAYCAST,
+    )
+    .into_iter()
+    .flatten()
+    .min_by(|a, b| a.distance.total_cmp(&b.distance));
+
+    state.raycast = state.hit.map_or(MAX_RAYCAST, |hit| hit.distance);
+
+    let distance = ops::powf(state.raycast / MAX_RAYCAST, 4.);
+    let speed_mul = 1. + (1. - distance) * 5.;
+
+    let mut direction = Vec3::ZERO;
+    if input.length() >= 0.1 {
+        direction += input.extend(0.);
+    } else if !matches!(state.time, TimeState::Finished(_)) {
+        friction = match state.time {
+            TimeState::Finished(_) => 0.97,
+            _ => 0.95,
+        };
+    }
+
+    if direction.length() > 0.0 {
+        state.velocity += direction * ACCELERATION * speed_mul * dt;
+    }
+
+    state.velocity *= ops::powf(friction, dt * 100.);
+}
+
+// moves the ship along its velocity, sliding along walls it hits, then turns it towards input
+fn apply_velocity(map: &DensityMap, state: &mut ShipState, input: Vec2, dt: f32) {
+    let start = state.transform;
+    let transform = &mut state.transform;
+    let velocity = &mut state.velocity;
+
+    let mut remaining = dt;
+    for _ in 0..MAX_SUBSTEPS {
+        let motion = velocity.xy() * remaining;
+        let length = motion.length();
+        if length < 1e-4 {
+            break;
+        }
+
+        let Some(hit) = sweep(map, transform, motion) else {
+            transform.translation += motion.extend(0.);
+            break;
+        };
+
+        // time of impact as a fraction of the remaining motion
+        let toi = hit.distance / length;
+        transform.translation += (motion / length * (hit.distance - SKIN).max(0.)).extend(0.);
+        remaining *= 1. - toi;
+
+        let into = velocity.xy().dot(hit.normal);
+        if into < 0. {
+            *velocity -= (hit.normal * into).extend(0.);
+        }
+    }
+
+    if input.length() >= 0.1 {
+        let target_angle = ops::atan2(input.y, input.x) - PI / 2.;
+        let target_rotation = Quat::from_rotation_z(target_angle);
+
+        transform.rotation = transform.rotation.slerp(
+            target_rotation,
+            (1. - ops::powf(0.002, dt)) * (0.2 + input.length() * 1.3),
+        );
+    }
+
+    depenetrate(map, transform, start);
+}
+
+// checkpoints count in order, and the run finishes once every one of them has been passed
+// and the ship reaches the finish line
+fn check_gates(track: &Track, state: &mut ShipState) {
+    let TimeState::Timing(ticks) = state.time else {
+        return;
+    };
+
+    if let Some(checkpoint) = track.checkpoints.get(state.checkpoint) {
+        if touches(&state.transform, checkpoint) {
+            state.checkpoint += 1;
+        }
+        return;
+    }
+
+    if let Some(end) = &track.end
+        && touches(&state.transform, end)
+    {
+        state.time = TimeState::Finished(ticks);
+    }
+}
+
+// whether any hull point is close enough to the line
+fn touches(transform: &Transform, line: &[[f32; 2]; 2]) -> bool {
+    let start = Vec2::from_array(line[0]);
+    let end = Vec2::from_array(line[1]);
+    hull_points(transform)
+        .into_iter()
+        .any(|point| dist_to_segment(point, start, end) < FINISH_DISTANCE)
+}
+
+// earliest hit of the hull outline moving along motion, ignoring surfaces it is leaving
+fn sweep(map: &DensityMap, transform: &Transform, motion: Vec2) -> Option<RaycastHit> {
+    let distance = motion.length();
+    let dir = motion / distance;
+    hull_samples(transform, map.scale() * 0.5)
+        .into_iter()
+        .filter_map(|point| Raycaster::raycast(map, point, dir, distance))
+        .filter(|hit| hit.normal.dot(dir) < 0.)
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+// deepest penetration of the hull into the terrain and the direction into it
+fn penetration(map: &DensityMap, transform: &Transform) -> Option<(f32, Vec3)> {
+    let mut depth: f32 = 0.;
+    let mut normal = Vec3::ZERO;
+    for point in hull_points(transform) {
+        if let Some(distance) = map.get_distance_world(point.x, point.y)
+            && distance < -PENETRATION_TOLERANCE
+            && let Some(pnormal) = map.get_normal_world(point.x, point.y)
+        {
+            depth = depth.max(-distance);
+            normal += Vec3::new(pnormal.0, pnormal.1, 0.);
+        }
+    }
+    match normal.length() > 0. {
+        true => Some((depth, normal.normalize_or_zero())),
+        false => None,
+    }
+}
+
+// pushes the hull out of the terrain, giving up and restoring fallback after a fixed number of steps
+fn depenetrate(map: &DensityMap, transform: &mut Transform, fallback: Transform) {
+    for _ in 0..MAX_DEPENETRATION_STEPS {
+        let Some((depth, normal)) = penetration(map, transform) else {
+            return;
+        };
+        transform.translation -= normal * (depth + SKIN * 0.5);
+    }
+
+    if penetration(map, transform).is_some() {
+        *transform = fallback;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level_format::GridParams;
+
+    // an open box 60 cells across, with a wall one cell thick at x = wall cells if given
+    fn arena(wall: Option<i32>) -> DensityMap {
+        let mut map = DensityMap::new(GridParams::default());
+        for x in -40..40_i32 {
+            for y in -40..40_i32 {
+                let open = x.abs() < 30 && y.abs() < 30 && Some(x) != wall;
+                map.set(x, y, if open { 0. } else { 1. });
+            }
+        }
+        map.update_sdf();
+        map
+    }
+
+    fn level() -> LevelData {
+        LevelData {
+            level: None,
+            start: [0., 0., 0.],
+            end: Some([[200., -100.], [200., 100.]]),
+            checkpoints: Vec::new(),
+            grid: Some(GridParams::default()),
+            generator: None,
+            ghost: None,
+        }
+    }
+
+    // steering that sweeps around the arena and into its walls
+    fn inputs() -> Vec<Vec2> {
+        (0..600)
+            .map(|tick| Vec2::from_angle(tick as f32 * 0.02) * (0.5 + (tick % 7) as f32 * 0.1))
+            .collect()
+    }
+
+    #[test]
+    fn simulate_is_deterministic() {
+        let map = arena(None);
+        let level = level();
+        let track = Track::new(&map, &level);
+
+        let a = simulate(&track, level.start, &inputs());
+        let b = simulate(&track, level.start, &inputs());
+
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            let bits = |state: &ShipState| {
+                let transform = state.transform;
+                (
+                    transform.translation.to_array().map(f32::to_bits),
+                    transform.rotation.to_array().map(f32::to_bits),
+                    state.velocity.to_array().map(f32::to_bits),
+                    state.time,
+                )
+            };
+            assert_eq!(bits(a), bits(b));
+        }
+    }
+
+    #[test]
+    fn fast_ship_does_not_pass_through_thin_wall() {
+        let wall = 10;
+        let map = arena(Some(wall));
+        let level = level();
+        let track = Track::new(&map, &level);
+
+        // covers several times the wall's thickness each tick
+        let mut state = ShipState::new(level.start);
+        state.velocity = Vec3::new(20000., 0., 0.);
+
+        let wall_x = wall as f32 * map.scale();
+        for _ in 0..20 {
+            state = step(&track, &state, Vec2::ZERO);
+            for point in hull_points(&state.transform) {
+                assert!(
+                    point.x < wall_x,
+                    "hull point {} got through the wall",
+                    point
+                );
+            }
+        }
+    }
+}
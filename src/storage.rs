@@ -0,0 +1,204 @@
+use std::{fmt, sync::Mutex};
+
+use bevy::{platform::collections::HashMap, prelude::*};
+
+// somewhere to keep save data between sessions, as text under keys like "records" or "levels/draft"
+pub trait Storage: Send + Sync {
+    fn read(&self, key: &str) -> Option<String>;
+    fn write(&self, key: &str, value: &str) -> Result<(), StorageError>;
+    fn remove(&self, key: &str) -> Result<(), StorageError>;
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    #[cfg(not(target_arch = "wasm32"))]
+    Io(std::io::Error),
+    // the browser refused, usually because storage is full or disabled
+    Rejected,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            StorageError::Io(error) => write!(f, "{}", error),
+            StorageError::Rejected => write!(f, "storage rejected the write"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+#[derive(Resource)]
+pub struct SaveStorage(pub Box<dyn Storage>);
+
+impl SaveStorage {
+    // the platform's own storage, or memory if it isn't available
+    pub fn platform() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let storage = FileStorage::new().map(|storage| Box::new(storage) as Box<dyn Storage>);
+        #[cfg(target_arch = "wasm32")]
+        let storage = LocalStorage::new().map(|storage| Box::new(storage) as Box<dyn Storage>);
+
+        SaveStorage(storage.unwrap_or_else(|| {
+            warn!("no storage available, nothing will be saved after closing the game");
+            Box::new(MemoryStorage::default())
+        }))
+    }
+}
+
+// one file per key under the platform data directory
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileStorage {
+    root: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStorage {
+    pub fn new() -> Option<Self> {
+        Some(FileStorage::at(dirs::data_dir()?.join("speedwing")))
+    }
+    pub fn at(root: std::path::PathBuf) -> Self {
+        Self { root }
+    }
+    fn path(&self, key: &str) -> std::path::PathBuf {
+        self.root.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Storage for FileStorage {
+    fn read(&self, key: &str) -> Option<String> {
+        std::fs::read_to_string(self.path(key)).ok()
+    }
+    fn write(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(StorageError::Io)?;
+        }
+        std::fs::write(path, value).map_err(StorageError::Io)
+    }
+    fn remove(&self, key: &str) -> Result<(), StorageError> {
+        match std::fs::remove_file(self.path(key)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(StorageError::Io(error))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// the browser's local storage, keys are prefixed so they don't clash with anything else on the page
+#[cfg(target_arch = "wasm32")]
+pub struct LocalStorage;
+
+#[cfg(target_arch = "wasm32")]
+const LOCAL_STORAGE_PREFIX: &str = "speedwing/";
+
+#[cfg(target_arch = "wasm32")]
+impl LocalStorage {
+    pub fn new() -> Option<Self> {
+        LocalStorage::storage().map(|_| LocalStorage)
+    }
+    // web_sys::Storage isn't Send, so it's looked up on every use instead of kept
+    fn storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Storage for LocalStorage {
+    fn read(&self, key: &str) -> Option<String> {
+        LocalStorage::storage()?
+            .get_item(&format!("{}{}", LOCAL_STORAGE_PREFIX, key))
+            .ok()?
+    }
+    fn write(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        LocalStorage::storage()
+            .ok_or(StorageError::Rejected)?
+            .set_item(&format!("{}{}", LOCAL_STORAGE_PREFIX, key), value)
+            .map_err(|_| StorageError::Rejected)
+    }
+    fn remove(&self, key: &str) -> Result<(), StorageError> {
+        LocalStorage::storage()
+            .ok_or(StorageError::Rejected)?
+            .remove_item(&format!("{}{}", LOCAL_STORAGE_PREFIX, key))
+            .map_err(|_| StorageError::Rejected)
+    }
+}
+
+// keeps everything until the game closes, for when there's nowhere else and for tools and tests
+#[derive(Default)]
+pub struct MemoryStorage {
+    values: Mutex<HashMap<String, String>>,
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, key: &str) -> Option<String> {
+        self.values.lock().ok()?.get(key).cloned()
+    }
+    fn write(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        if let Ok(mut values) = self.values.lock() {
+            values.insert(key.to_string(), value.to_string());
+        }
+        Ok(())
+    }
+    fn remove(&self, key: &str) -> Result<(), StorageError> {
+        if let Ok(mut values) = self.values.lock() {
+            values.remove(key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trips(storage: &dyn Storage) {
+        assert_eq!(storage.read("replays/last"), None);
+        storage.write("replays/last", "first").unwrap();
+        assert_eq!(storage.read("replays/last").as_deref(), Some("first"));
+        storage.write("replays/last", "second").unwrap();
+        assert_eq!(storage.read("replays/last").as_deref(), Some("second"));
+        storage.remove("replays/last").unwrap();
+        assert_eq!(storage.read("replays/last"), None);
+    }
+
+    fn removes_missing_keys(storage: &dyn Storage) {
+        assert!(storage.remove("records").is_ok());
+        assert!(storage.remove("levels/user/1").is_ok());
+        assert_eq!(storage.read("records"), None);
+    }
+
+    // a directory of its own under the system temp directory, removed afterwards
+    #[cfg(not(target_arch = "wasm32"))]
+    fn with_file_storage(name: &str, test: fn(&dyn Storage)) {
+        let root =
+            std::env::temp_dir().join(format!("speedwing-storage-{}-{}", std::process::id(), name));
+        test(&FileStorage::at(root.clone()));
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn memory_storage_round_trips() {
+        round_trips(&MemoryStorage::default());
+    }
+
+    #[test]
+    fn memory_storage_removes_missing_keys() {
+        removes_missing_keys(&MemoryStorage::default());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn file_storage_round_trips() {
+        with_file_storage("round-trip", round_trips);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn file_storage_removes_missing_keys() {
+        with_file_storage("remove-missing", removes_missing_keys);
+    }
+}
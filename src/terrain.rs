@@ -0,0 +1,367 @@
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti};
+use serde::{Deserialize, Serialize};
+
+// densities go from 0 (open) to 1 (solid) and are sampled at grid points, in cells
+pub trait TerrainGenerator: Send + Sync {
+    fn sample(&self, x: f64, y: f64) -> f64;
+
+    // a width x height block of points starting at x, y, indexed y * width + x
+    fn fill(&self, x: i32, y: i32, width: u32, height: u32) -> Vec<f64> {
+        let mut vs = Vec::with_capacity((width * height) as usize);
+        for py in 0..height as i32 {
+            for px in 0..width as i32 {
+                vs.push(self.sample((x + px) as f64, (y + py) as f64));
+            }
+        }
+        vs
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GeneratorConfig {
+    // single octave perlin, squared to thin out the solid areas
+    Perlin {
+        frequency: f64,
+    },
+    Fbm {
+        frequency: f64,
+        octaves: usize,
+        lacunarity: f64,
+        persistence: f64,
+    },
+    Ridged {
+        frequency: f64,
+        octaves: usize,
+        lacunarity: f64,
+    },
+    // open rooms around scattered points
+    Worley {
+        frequency: f64,
+    },
+    // random fill smoothed into caves by the 4-5 rule
+    CellularAutomata {
+        fill: f64,
+        steps: u32,
+    },
+    // samples source at coordinates pushed around by noise
+    Warp {
+        frequency: f64,
+        strength: f64,
+        source: Box<GeneratorConfig>,
+    },
+    Min {
+        layers: Vec<GeneratorConfig>,
+    },
+    Max {
+        layers: Vec<GeneratorConfig>,
+    },
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig::Perlin { frequency: 0.05 }
+    }
+}
+
+impl GeneratorConfig {
+    pub fn build(&self, seed: u32) -> Box<dyn TerrainGenerator> {
+        match self {
+            GeneratorConfig::Perlin { frequency } => Box::new(PerlinGenerator {
+                perlin: Perlin::new(seed),
+                frequency: *frequency,
+            }),
+            GeneratorConfig::Fbm {
+                frequency,
+                octaves,
+                lacunarity,
+                persistence,
+            } => Box::new(FbmGenerator(
+                Fbm::<Perlin>::new(seed)
+                    .set_frequency(*frequency)
+                    .set_octaves(*octaves)
+                    .set_lacunarity(*lacunarity)
+                    .set_persistence(*persistence),
+            )),
+            GeneratorConfig::Ridged {
+                frequency,
+                octaves,
+                lacunarity,
+            } => Box::new(RidgedGenerator(
+                RidgedMulti::<Perlin>::new(seed)
+                    .set_frequency(*frequency)
+                    .set_octaves(*octaves)
+                    .set_lacunarity(*lacunarity),
+            )),
+            GeneratorConfig::Worley { frequency } => Box::new(WorleyGenerator {
+                seed,
+                frequency: *frequency,
+            }),
+            GeneratorConfig::CellularAutomata { fill, steps } => Box::new(CellularGenerator {
+                seed,
+                fill: *fill,
+                steps: *steps,
+            }),
+            GeneratorConfig::Warp {
+                frequency,
+                strength,
+                source,
+            } => Box::new(WarpGenerator {
+                x: Perlin::new(seed.wrapping_add(1)),
+                y: Perlin::new(seed.wrapping_add(2)),
+                frequency: *frequency,
+                strength: *strength,
+                source: source.build(seed),
+            }),
+            GeneratorConfig::Min { layers } => Box::new(CombineGenerator {
+                layers: build_layers(layers, seed),
+                combine: f64::min,
+            }),
+            GeneratorConfig::Max { layers } => Box::new(CombineGenerator {
+                layers: build_layers(layers, seed),
+                combine: f64::max,
+            }),
+        }
+    }
+
+    // named generators to pick from in the editor and endless mode
+    pub fn presets() -> Vec<(&'static str, GeneratorConfig)> {
+        vec![
+            ("perlin", GeneratorConfig::default()),
+            (
+                "fbm",
+                GeneratorConfig::Fbm {
+                    frequency: 0.03,
+                    octaves: 4,
+                    lacunarity: 2.,
+                    persistence: 0.5,
+                },
+            ),
+            (
+                "ridged",
+                GeneratorConfig::Ridged {
+                    frequency: 0.02,
+                    octaves: 4,
+                    lacunarity: 2.,
+                },
+            ),
+            ("worley caves", GeneratorConfig::Worley { frequency: 0.04 }),
+            (
+                "cellular caves",
+                GeneratorConfig::CellularAutomata {
+                    fill: 0.45,
+                    steps: 5,
+                },
+            ),
+            (
+                "warped fbm",
+                GeneratorConfig::Warp {
+                    frequency: 0.02,
+                    strength: 12.,
+                    source: Box::new(GeneratorConfig::Fbm {
+                        frequency: 0.03,
+                        octaves: 4,
+                        lacunarity: 2.,
+                        persistence: 0.5,
+                    }),
+                },
+            ),
+        ]
+    }
+}
+
+// every layer gets its own seed so stacking the same generator doesn't just repeat it
+fn build_layers(layers: &[GeneratorConfig], seed: u32) -> Vec<Box<dyn TerrainGenerator>> {
+    layers
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| layer.build(seed.wrapping_add(i as u32 * 101)))
+        .collect()
+}
+
+// a generator and the seed it was built with, which is enough to regenerate a map
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GeneratorSettings {
+    pub seed: u32,
+    pub config: GeneratorConfig,
+}
+
+impl GeneratorSettings {
+    pub fn build(&self) -> Box<dyn TerrainGenerator> {
+        self.config.build(self.seed)
+    }
+}
+
+// noise output in -1..1 mapped to 0..1
+fn unit(v: f64) -> f64 {
+    ((v + 1.) / 2.).clamp(0., 1.)
+}
+
+fn hash(seed: u32, x: i32, y: i32) -> u32 {
+    let mut h = seed
+        .wrapping_mul(0x9e37_79b9)
+        .wrapping_add((x as u32).wrapping_mul(0x85eb_ca6b))
+        .wrapping_add((y as u32).wrapping_mul(0xc2b2_ae35));
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+fn hash_unit(seed: u32, x: i32, y: i32) -> f64 {
+    hash(seed, x, y) as f64 / u32::MAX as f64
+}
+
+struct PerlinGenerator {
+    perlin: Perlin,
+    frequency: f64,
+}
+
+impl TerrainGenerator for PerlinGenerator {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        unit(self.perlin.get([x * self.frequency, y * self.frequency])).powf(2.)
+    }
+}
+
+struct FbmGenerator(Fbm<Perlin>);
+
+impl TerrainGenerator for FbmGenerator {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        unit(self.0.get([x, y]))
+    }
+}
+
+struct RidgedGenerator(RidgedMulti<Perlin>);
+
+impl TerrainGenerator for RidgedGenerator {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        unit(self.0.get([x, y]))
+    }
+}
+
+struct WorleyGenerator {
+    seed: u32,
+    frequency: f64,
+}
+
+impl TerrainGenerator for WorleyGenerator {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let (x, y) = (x * self.frequency, y * self.frequency);
+        let (cx, cy) = (x.floor() as i32, y.floor() as i32);
+
+        // distance to the nearest point, one jittered point per cell
+        let mut nearest = f64::MAX;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let (px, py) = (cx + dx, cy + dy);
+                let fx = px as f64 + hash_unit(self.seed, px, py);
+                let fy = py as f64 + hash_unit(self.seed.wrapping_add(1), px, py);
+                nearest = nearest.min((fx - x).hypot(fy - y));
+            }
+        }
+
+        (nearest / 0.8).min(1.)
+    }
+}
+
+struct CellularGenerator {
+    seed: u32,
+    fill: f64,
+    steps: u32,
+}
+
+impl TerrainGenerator for CellularGenerator {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        self.fill(x.floor() as i32, y.floor() as i32, 1, 1)[0]
+    }
+
+    // runs the automaton on a padded block, far enough out that the edges
+    // can't reach the requested points, so blocks agree wherever they overlap
+    fn fill(&self, x: i32, y: i32, width: u32, height: u32) -> Vec<f64> {
+        let pad = self.steps as i32 + 1;
+        let w = width as i32 + pad * 2;
+        let h = height as i32 + pad * 2;
+
+        let mut cells: Vec<bool> = (0..h)
+            .flat_map(|cy| (0..w).map(move |cx| (cx, cy)))
+            .map(|(cx, cy)| hash_unit(self.seed, x - pad + cx, y - pad + cy) < self.fill)
+            .collect();
+
+        let solid_around = |cells: &[bool], cx: i32, cy: i32| {
+            let mut count = 0;
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let (nx, ny) = (cx + dx, cy + dy);
+                    // outside the block counts as solid, its effect stays inside the padding
+                    if nx < 0 || ny < 0 || nx >= w || ny >= h || cells[(ny * w + nx) as usize] {
+                        count += 1;
+                    }
+                }
+            }
+            count
+        };
+
+        for _ in 0..self.steps {
+            cells = (0..h)
+                .flat_map(|cy| (0..w).map(move |cx| (cx, cy)))
+                .map(|(cx, cy)| solid_around(&cells, cx, cy) >= 5)
+                .collect();
+        }
+
+        // fraction of solid cells around each point gives smoother edges than 0 or 1
+        let mut vs = Vec::with_capacity((width * height) as usize);
+        for py in 0..height as i32 {
+            for px in 0..width as i32 {
+                vs.push(solid_around(&cells, px + pad, py + pad) as f64 / 9.);
+            }
+        }
+        vs
+    }
+}
+
+struct WarpGenerator {
+    x: Perlin,
+    y: Perlin,
+    frequency: f64,
+    strength: f64,
+    source: Box<dyn TerrainGenerator>,
+}
+
+impl TerrainGenerator for WarpGenerator {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let point = [x * self.frequency, y * self.frequency];
+        self.source.sample(
+            x + self.x.get(point) * self.strength,
+            y + self.y.get(point) * self.strength,
+        )
+    }
+}
+
+struct CombineGenerator {
+    layers: Vec<Box<dyn TerrainGenerator>>,
+    combine: fn(f64, f64) -> f64,
+}
+
+impl TerrainGenerator for CombineGenerator {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        self.layers
+            .iter()
+            .map(|layer| layer.sample(x, y))
+            .reduce(self.combine)
+            .unwrap_or(0.)
+    }
+
+    fn fill(&self, x: i32, y: i32, width: u32, height: u32) -> Vec<f64> {
+        self.layers
+            .iter()
+            .map(|layer| layer.fill(x, y, width, height))
+            .reduce(|a, b| {
+                a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| (self.combine)(a, b))
+                    .collect()
+            })
+            .unwrap_or_else(|| vec![0.; (width * height) as usize])
+    }
+}
@@ -0,0 +1,354 @@
+// triangles for a chunk of the density map, built off the main thread and
+// turned into a mesh by grid
+
+use bevy::platform::collections::HashMap;
+
+use crate::density::{Chunk, DensityMap, saddle_solid};
+
+// corners in cells from the chunk's origin
+type Triangle = ((f32, f32), (f32, f32), (f32, f32));
+
+// solid cells this deep into the terrain shade flat, so they can be merged into
+// larger quads without changing how they look
+const MERGE_MIN_V: f32 = 0.8;
+const MERGE_MIN_DEPTH: f32 = 1.;
+
+pub struct MeshAttributes {
+    pub positions: Vec<[f32; 3]>,
+    pub colours: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    pub vs: Vec<f32>,
+    pub ds: Vec<f32>,
+    // vertices the mesh would have had with one vertex per triangle corner and no merging
+    pub unmerged_vertices: usize,
+}
+
+impl MeshAttributes {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+// copies of a chunk and the +x+y, +x and +y neighbours its last row and
+// column of cells bridge into, for meshing off the main thread
+pub struct MeshSnapshot {
+    pub chunk: Chunk,
+    pub bridges: [Option<Chunk>; 3],
+}
+
+impl MeshSnapshot {
+    pub fn new(map: &DensityMap, gx: i32, gy: i32) -> Option<Self> {
+        Some(Self {
+            chunk: map.chunk(gx, gy)?.clone(),
+            bridges: [(1, 1), (1, 0), (0, 1)].map(|(dx, dy)| map.chunk(gx + dx, gy + dy).cloned()),
+        })
+    }
+    fn get(&self, x: u32, y: u32) -> Option<f32> {
+        self.get_with(x, y, Chunk::get)
+    }
+    fn get_with(&self, x: u32, y: u32, get: fn(&Chunk, u32, u32) -> Option<f32>) -> Option<f32> {
+        let chunk = &self.chunk;
+        match get(chunk, x, y) {
+            Some(v) => Some(v),
+            None => {
+                if x >= chunk.width
+                    && y >= chunk.height
+                    && let Some(bridge) = &self.bridges[0]
+                {
+                    get(bridge, x - chunk.width, y - chunk.height)
+                } else if x >= chunk.width
+                    && let Some(bridge) = &self.bridges[1]
+                {
+                    get(bridge, x - chunk.width, y)
+                } else if y >= chunk.height
+                    && let Some(bridge) = &self.bridges[2]
+                {
+                    get(bridge, x, y - chunk.height)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+    fn gets_with(&self, x: f32, y: f32, get: fn(&Chunk, u32, u32) -> Option<f32>) -> Option<f32> {
+        let x0 = x.floor() as u32;
+        let y0 = y.floor() as u32;
+        let x1 = x0 + 1;
+        let y1 = y0 + 1;
+
+        let tx = x.fract();
+        let ty = y.fract();
+
+        let v00 = self.get_with(x0, y0, get)?;
+        let v10 = self.get_with(x1, y0, get)?;
+        let v01 = self.get_with(x0, y1, get)?;
+        let v11 = self.get_with(x1, y1, get)?;
+
+        let v = (v00 * (1. - tx) + v10 * tx) * (1. - ty) + (v01 * (1. - tx) + v11 * tx) * ty;
+
+        Some(v)
+    }
+    pub fn gen_triangles(&self, threshold: f32, smooth: bool, merged: &[bool]) -> Vec<Triangle> {
+        let mut triangles = Vec::new();
+
+        for x in 0..self.chunk.width {
+            for y in 0..self.chunk.height {
+                if merged[(y * self.chunk.width + x) as usize] {
+                    continue;
+                }
+
+                let vs = [
+                    self.get(x, y),
+                    self.get(x + 1, y),
+                    self.get(x, y + 1),
+                    self.get(x + 1, y + 1),
+                ];
+                let mut vsf = [0., 0., 0., 0.];
+                let mut vi = 0u8;
+                for (i, v) in vs.iter().enumerate() {
+                    let b = v.is_some_and(|v| v > threshold);
+                    vsf[i] = v.unwrap_or(0.);
+                    vi |= (b as u8) << (i as u8);
+                }
+
+                let x = x as f32;
+                let y = y as f32;
+
+                let rs = match smooth {
+                    true => (
+                        ((threshold - vsf[0]) / (vsf[1] - vsf[0])).clamp(0., 1.),
+                        ((threshold - vsf[1]) / (vsf[3] - vsf[1])).clamp(0., 1.),
+                        ((threshold - vsf[2]) / (vsf[3] - vsf[2])).clamp(0., 1.),
+                        ((threshold - vsf[0]) / (vsf[2] - vsf[0])).clamp(0., 1.),
+                    ),
+                    false => (0.5, 0.5, 0.5, 0.5),
+                };
+
+                let c = ((x, y), (x + 1., y), (x + 1., y + 1.), (x, y + 1.));
+
+                let e = (
+                    (x + rs.0, y),
+                    (x + 1., y + rs.1),
+                    (x + rs.2, y + 1.),
+                    (x, y + rs.3),
+                );
+
+                if let Some(mut triangle) = match vi {
+                    // corners
+                    0b0001 => Some(vec![(e.0, e.3, c.0)]),
+                    0b0010 => Some(vec![(e.0, c.1, e.1)]),
+                    0b0100 => Some(vec![(e.3, e.2, c.3)]),
+                    0b1000 => Some(vec![(e.1, c.2, e.2)]),
+
+                    // big corners
+                    0b0111 => Some(vec![(c.0, c.1, e.1), (c.0, e.1, e.2), (c.0, e.2, c.3)]),
+                    0b1011 => Some(vec![(c.1, c.2, e.2), (c.1, e.2, e.3), (c.1, e.3, c.0)]),
+                    0b1101 => Some(vec![(c.3, c.0, e.0), (c.3, e.0, e.1), (c.3, e.1, c.2)]),
+                    0b1110 => Some(vec![(c.2, c.3, e.3), (c.2, e.3, e.0), (c.2, e.0, c.1)]),
+
+                    // edges
+                    0b0011 => Some(vec![(c.0, c.1, e.1), (c.0, e.1, e.3)]),
+                    0b1100 => Some(vec![(e.1, c.2, c.3), (e.1, c.3, e.3)]),
+                    0b0101 => Some(vec![(c.0, e.0, e.2), (c.0, e.2, c.3)]),
+                    0b1010 => Some(vec![(c.1, c.2, e.2), (c.1, e.2, e.0)]),
+
+                    // diagonals, joined through the centre when it is solid
+                    0b1001 | 0b0110 if saddle_solid(vsf, threshold) => match vi {
+                        0b1001 => Some(vec![
+                            (c.0, e.0, e.1),
+                            (c.0, e.1, c.2),
+                            (c.0, c.2, e.2),
+                            (c.0, e.2, e.3),
+                        ]),
+                        _ => Some(vec![
+                            (c.1, e.1, e.2),
+                            (c.1, e.2, c.3),
+                            (c.1, c.3, e.3),
+                            (c.1, e.3, e.0),
+                        ]),
+                    },
+                    0b1001 => Some(vec![(c.0, e.0, e.3), (e.1, c.2, e.2)]),
+                    0b0110 => Some(vec![(c.1, e.1, e.0), (c.3, e.3, e.2)]),
+
+                    0b1111 => Some(vec![(c.0, c.1, c.2), (c.0, c.2, c.3)]),
+
+                    _ => None,
+                } {
+                    triangles.append(&mut triangle);
+                }
+            }
+        }
+
+        triangles
+    }
+
+    // solid cells deep enough into the terrain to be drawn as part of a merged quad
+    fn mergeable_cells(&self, threshold: f32) -> Vec<bool> {
+        let mut mergeable = vec![false; (self.chunk.width * self.chunk.height) as usize];
+        for x in 0..self.chunk.width {
+            for y in 0..self.chunk.height {
+                mergeable[(y * self.chunk.width + x) as usize] =
+                    [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                        .into_iter()
+                        .all(|(x, y)| {
+                            self.get(x, y)
+                                .is_some_and(|v| (v - threshold) / (1. - threshold) >= MERGE_MIN_V)
+                                && self
+                                    .get_with(x, y, Chunk::get_sdf)
+                                    .is_some_and(|d| d <= -MERGE_MIN_DEPTH)
+                        });
+            }
+        }
+        mergeable
+    }
+    // greedily covers the mergeable cells with rectangles of x, y, width, height,
+    // keeping only the ones that fan into fewer triangles and vertices than their cells
+    fn merge_cells(&self, mergeable: &[bool]) -> Vec<(u32, u32, u32, u32)> {
+        let mut covered = vec![false; mergeable.len()];
+        let free = |covered: &[bool], x: u32, y: u32| {
+            let i = (y * self.chunk.width + x) as usize;
+            mergeable[i] && !covered[i]
+        };
+
+        let mut quads = Vec::new();
+        for y in 0..self.chunk.height {
+            for x in 0..self.chunk.width {
+                if !free(&covered, x, y) {
+                    continue;
+                }
+
+                let mut w = 1;
+                while x + w < self.chunk.width && free(&covered, x + w, y) {
+                    w += 1;
+                }
+                let mut h = 1;
+                while y + h < self.chunk.height && (x..x + w).all(|cx| free(&covered, cx, y + h)) {
+                    h += 1;
+                }
+
+                for cy in y..y + h {
+                    for cx in x..x + w {
+                        covered[(cy * self.chunk.width + cx) as usize] = true;
+                    }
+                }
+                if (w - 1) * (h - 1) > 1 {
+                    quads.push((x, y, w, h));
+                }
+            }
+        }
+        quads
+    }
+    pub fn gen_attributes(&self, threshold: f32, smooth: bool) -> MeshAttributes {
+        let mut positions = Vec::new();
+        let mut colours = Vec::new();
+        let mut indices = Vec::new();
+        let mut vs = Vec::new();
+        let mut ds = Vec::new();
+
+        let quads = self.merge_cells(&self.mergeable_cells(threshold));
+        let mut merged = vec![false; (self.chunk.width * self.chunk.height) as usize];
+        for (x, y, w, h) in quads.iter().copied() {
+            for cy in y..y + h {
+                for cx in x..x + w {
+                    merged[(cy * self.chunk.width + cx) as usize] = true;
+                }
+            }
+        }
+
+        let mut triangles = self.gen_triangles(threshold, smooth, &merged);
+        let merged_cells = merged.iter().filter(|merged| **merged).count();
+        let unmerged_vertices = (triangles.len() + merged_cells * 2) * 3;
+
+        // the cells around a quad have vertices at every point along its edges, so
+        // it's fanned from its centre through all of them to avoid T-junctions
+        for (x, y, w, h) in quads {
+            let (x0, y0) = (x as f32, y as f32);
+            let (x1, y1) = ((x + w) as f32, (y + h) as f32);
+            let centre = ((x0 + x1) / 2., (y0 + y1) / 2.);
+
+            let outline: Vec<(f32, f32)> = (x..x + w)
+                .map(|cx| (cx as f32, y0))
+                .chain((y..y + h).map(|cy| (x1, cy as f32)))
+                .chain((x + 1..=x + w).rev().map(|cx| (cx as f32, y1)))
+                .chain((y + 1..=y + h).rev().map(|cy| (x0, cy as f32)))
+                .collect();
+            for (i, point) in outline.iter().enumerate() {
+                let next = outline[(i + 1) % outline.len()];
+                triangles.push((centre, *point, next));
+            }
+        }
+
+        // neighbouring cells compute shared points from the same values, so
+        // matching bits means the same vertex
+        let mut vertices: HashMap<(u32, u32), u32> = HashMap::new();
+        for triangle in triangles.iter() {
+            for point in [triangle.0, triangle.1, triangle.2] {
+                let index = *vertices
+                    .entry((point.0.to_bits(), point.1.to_bits()))
+                    .or_insert_with(|| {
+                        positions.push([point.0, point.1, 0.]);
+                        colours.push([0.5, 0.5, 0.5, 1.]);
+                        vs.push(match self.gets_with(point.0, point.1, Chunk::get) {
+                            Some(v) => (v - threshold) / (1. - threshold),
+                            None => 0.,
+                        });
+                        ds.push(
+                            self.gets_with(point.0, point.1, Chunk::get_sdf)
+                                .map_or(0., |d| -d),
+                        );
+                        positions.len() as u32 - 1
+                    });
+                indices.push(index);
+            }
+        }
+
+        MeshAttributes {
+            positions,
+            colours,
+            indices,
+            vs,
+            ds,
+            unmerged_vertices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level_asset::LevelAsset;
+
+    #[test]
+    fn shipped_levels_share_vertices() {
+        let mut paths: Vec<_> = std::fs::read_dir("assets/levels")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".level.json"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let level = LevelAsset::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            let map = &level.map;
+            let (mut vertices, mut indices, mut unmerged) = (0, 0, 0);
+            for ((gx, gy), _) in map.chunks() {
+                let attributes = MeshSnapshot::new(map, gx, gy)
+                    .unwrap()
+                    .gen_attributes(map.threshold(), map.smooth());
+                vertices += attributes.vertex_count();
+                indices += attributes.indices.len();
+                unmerged += attributes.unmerged_vertices;
+            }
+
+            println!(
+                "{}: {} vertices and {} indices, {} vertices without sharing and merging",
+                path.display(),
+                vertices,
+                indices,
+                unmerged
+            );
+            assert!(vertices < unmerged, "{}", path.display());
+            assert!(indices <= unmerged, "{}", path.display());
+        }
+    }
+}